@@ -1,8 +1,9 @@
-use amethyst::ecs::{HashMapStorage,VecStorage,Component};
-use nalgebra::{Vector2,zero};
-use ncollide::shape::ShapeHandle2;
+use amethyst::ecs::{Entity,HashMapStorage,VecStorage,Component};
+use nalgebra::{Point2,Vector2,zero};
+use ncollide::shape::{ConvexHull,Cuboid,ShapeHandle2};
 
 use data::*;
+use util;
 
 pub struct Position(pub Vector2<f64>);
 impl Component for Position{
@@ -12,31 +13,94 @@ impl Component for Position{
 pub struct CollisionCache{
 	pub position_resolve: Vector2<f64>,
 	pub velocity_resolve: Vector2<f64>,
-	pub friction_total  : f64,
+	pub contacts        : Vec<Contact>,
 }
 impl CollisionCache{
 	pub fn new() -> Self{CollisionCache{
 		position_resolve: zero(),
 		velocity_resolve: zero(),
-		friction_total  : zero(),
+		contacts        : Vec::new(),
 	}}
 }
 impl Component for CollisionCache{
 	type Storage = VecStorage<CollisionCache>;
 }
 
+//What an entity is standing on. Updated by the physics every step
+pub struct GroundContact{
+	pub entity  : Option<Entity>, //The supporting entity. None when airborne
+	pub normal  : Vector2<f64>,   //Surface normal of the ground, pointing away from it
+	pub velocity: Vector2<f64>,   //Velocity of the ground
+	pub time    : f64,            //How long the entity has been grounded or airborne (seconds)
+}
+impl GroundContact{
+	pub fn new() -> Self{GroundContact{
+		entity  : None,
+		normal  : zero(),
+		velocity: zero(),
+		time    : 0.0,
+	}}
+
+	#[inline(always)]
+	pub fn is_grounded(&self) -> bool{
+		self.entity.is_some()
+	}
+}
+impl Component for GroundContact{
+	type Storage = VecStorage<GroundContact>;
+}
+
+//The wall an entity is touching on either side. Updated by the physics every step
+pub struct WallContact{
+	pub entity : Option<Entity>, //The touched wall. None when not touching any
+	pub normal : Vector2<f64>,   //Surface normal of the wall, pointing away from it
+	pub time   : f64,            //How long the entity has been touching or not touching a wall (seconds)
+	pub sliding: bool,           //Set by the controller when holding towards the wall. The physics limits the fall speed to the wall slide speed of the tuning while touching it
+}
+impl WallContact{
+	pub fn new() -> Self{WallContact{
+		entity : None,
+		normal : zero(),
+		time   : 0.0,
+		sliding: false,
+	}}
+
+	#[inline(always)]
+	pub fn is_touching(&self) -> bool{
+		self.entity.is_some()
+	}
+}
+impl Component for WallContact{
+	type Storage = VecStorage<WallContact>;
+}
+
 pub struct Solid{
 	pub typ           : SolidType,
 	pub check_movement: bool,
 	pub gravity       : bool,
-	pub friction      : f64,
+	pub material      : Material,
 	pub shape         : ShapeHandle2<f64>,
+	pub surface_velocity: f64, //pixels/seconds. Speed of the surface moving whatever touches it, for example conveyor belts. Positive values move clockwise around the shape (to the right on top)
+	pub fall_through  : bool, //Whether to ignore fall-through platforms, for example when dropping down from them
+	pub fast          : bool, //Whether to always use continuous collision detection, for example for projectiles
+	pub layer         : u32,  //The collision layers this is in (see data::layer)
+	pub mask          : u32,  //The collision layers this collides with
+	pub ignore        : Option<Entity>, //Never collides with this entity, for example the owner of a projectile
+
+	//Gravity data
+	pub gravity_scale    : f64,                  //Multiplier of the gravity. Negative values reverses it
+	pub gravity_direction: Option<Vector2<f64>>, //Direction of the gravity (unit vector) replacing the direction from the world or gravity zones
+	pub current_gravity  : Vector2<f64>,         //The gravity acceleration from the last physics step, even when gravity is disabled
+	pub submerged        : f64,                  //How much of the shape was inside a fluid in the last physics step (0.0 to 1.0)
 
 	//Movement data
-	pub velocity      : Vector2<f64>,
-	pub acceleration  : Vector2<f64>,//TODO: This is not used in the same way as velocity is. It is rather a temporary acceleration variable for each step.
+	pub velocity        : Vector2<f64>,
+	pub acceleration    : Vector2<f64>,//TODO: This is not used in the same way as velocity is. It is rather a temporary acceleration variable for each frame, applied to every physics step of the frame.
+	pub angle           : f64, //Orientation in radians
+	pub angular_velocity: f64, //radians/seconds
 
 	//Movement data from the previous step
+	pub old_angle       : f64,
 	pub old_position    : Vector2<f64>, //TODO: These may not be neccessary? Or maybe they are?
 	pub old_velocity    : Vector2<f64>,
 	pub old_acceleration: Vector2<f64>,
@@ -46,30 +110,421 @@ impl Solid{
 		typ           : SolidType,
 		check_movement: bool,
 		gravity       : bool,
-		friction      : f64,
+		material      : Material,
 		shape         : ShapeHandle2<f64>,
 	) -> Self{Solid{
 		typ           : typ,
 		check_movement: check_movement,
 		gravity       : gravity,
-		friction      : friction,
+		material      : material,
 		shape         : shape,
+		surface_velocity: 0.0,
+		fall_through  : false,
+		fast          : false,
+		layer         : layer::TERRAIN,
+		mask          : layer::ALL,
+		ignore        : None,
+
+		gravity_scale    : 1.0,
+		gravity_direction: None,
+		current_gravity  : zero(),
+		submerged        : 0.0,
 
-		velocity    : zero(),
-		acceleration: zero(),
+		velocity        : zero(),
+		acceleration    : zero(),
+		angle           : 0.0,
+		angular_velocity: 0.0,
 
+		old_angle       : 0.0,
 		old_position    : zero(),
 		old_velocity    : zero(),
 		old_acceleration: zero(),
 	}}
+
+	//Direction of the gravity, or downwards when there is none
+	#[inline(always)]
+	pub fn down(&self) -> Vector2<f64>{
+		util::gravity_down(self.current_gravity)
+	}
+
+	#[inline(always)]
+	pub fn up(&self) -> Vector2<f64>{
+		-self.down()
+	}
+
+	//Whether the two solids should collide with each other
+	pub fn collides_with(&self,entity: Entity,other: &Solid,other_entity: Entity) -> bool{
+		(self.mask & other.layer)!=0 && (other.mask & self.layer)!=0 &&
+		self.ignore!=Some(other_entity) && other.ignore!=Some(entity)
+	}
 }
 impl Component for Solid{
 	type Storage = VecStorage<Solid>;
 }
 
+//A volume detecting the solids overlapping it without pushing them out
+pub struct Trigger{
+	pub shape : ShapeHandle2<f64>,
+	pub mask  : u32,         //The collision layers of the solids to detect
+	pub inside: Vec<Entity>, //The solids overlapping the volume in the last physics step
+}
+impl Trigger{
+	pub fn new(shape: ShapeHandle2<f64>,mask: u32) -> Self{Trigger{
+		shape : shape,
+		mask  : mask,
+		inside: Vec::new(),
+	}}
+}
+impl Component for Trigger{
+	type Storage = HashMapStorage<Trigger>;
+}
+
+//Moves a solid along a path of waypoints. The velocity is derived from the path every physics step
+pub struct PathMover{
+	pub waypoints: Vec<Waypoint>,
+	pub mode     : PathMode,
+	pub previous : usize, //Index of the waypoint moved from
+	pub current  : usize, //Index of the waypoint moved towards
+	pub forward  : bool,  //Whether moving forward in the list of waypoints
+	pub wait_time: f64,   //Time left to wait at the current stop
+	pub finished : bool,
+}
+impl PathMover{
+	//Starts at the first waypoint
+	pub fn new(waypoints: Vec<Waypoint>,mode: PathMode) -> Self{PathMover{
+		wait_time: waypoints.first().map_or(0.0,|waypoint| waypoint.wait),
+		waypoints: waypoints,
+		mode     : mode,
+		previous : 0,
+		current  : 1,
+		forward  : true,
+		finished : false,
+	}}
+
+	//The velocity to move towards the current waypoint in a step of `delta_time` seconds. Advances to the next waypoint when arriving
+	pub fn step(&mut self,position: Vector2<f64>,delta_time: f64) -> Vector2<f64>{
+		if self.finished || self.waypoints.len() < 2{
+			return zero();
+		}
+		if self.wait_time > 0.0{
+			self.wait_time-= delta_time;
+			return zero();
+		}
+
+		//The timing of a segment is stored in the waypoint with the lowest index (or the last one when looping back to the first)
+		let from  = self.waypoints[self.previous].position;
+		let to    = self.waypoints[self.current].position;
+		let speed = match self.waypoints[if self.forward{self.previous}else{self.current}].timing{
+			PathTiming::Speed(speed)       => speed,
+			PathTiming::Duration(duration) => (to - from).norm() / duration,
+		};
+
+		let offset   = to - position;
+		let distance = offset.norm();
+		if distance <= speed*delta_time{
+			self.arrive();
+			offset / delta_time
+		}else{
+			offset * (speed / distance)
+		}
+	}
+
+	fn arrive(&mut self){
+		let last = self.waypoints.len()-1;
+		self.wait_time = self.waypoints[self.current].wait;
+
+		match self.mode{
+			PathMode::Loop =>{
+				self.previous = self.current;
+				self.current  = (self.current + 1) % self.waypoints.len();
+			}
+			PathMode::Once =>{
+				if self.current==last{
+					self.finished = true;
+				}else{
+					self.previous = self.current;
+					self.current += 1;
+				}
+			}
+			PathMode::PingPong =>{
+				if self.forward && self.current==last{
+					self.forward = false;
+				}else if !self.forward && self.current==0{
+					self.forward = true;
+				}
+				self.previous = self.current;
+				self.current  = if self.forward{self.current + 1}else{self.current - 1};
+			}
+		}
+	}
+}
+impl Component for PathMover{
+	type Storage = HashMapStorage<PathMover>;
+}
+
+//Replaces the gravity of the solids inside the trigger of the same entity
+pub struct GravityZone{
+	pub gravity : Vector2<f64>, //pixels/seconds^2
+	pub priority: i32,          //The zone with the highest priority is used when overlapping
+}
+impl Component for GravityZone{
+	type Storage = HashMapStorage<GravityZone>;
+}
+
+//Makes the trigger of the same entity a fluid, for example water. Solids inside it are pushed up by the buoyancy and slowed down by the drag
+pub struct Fluid{
+	pub buoyancy: f64, //Multiple of the gravity pushing against it when completely submerged. Solids float when this is more than 1.0
+	pub drag    : f64, //Fraction of the velocity lost every second when completely submerged (1/seconds). Replaces the air friction
+}
+impl Component for Fluid{
+	type Storage = HashMapStorage<Fluid>;
+}
+
+//Makes the trigger of the same entity a zone that players can climb in, for example a ladder
+pub struct Climbable;
+impl Component for Climbable{
+	type Storage = HashMapStorage<Climbable>;
+}
+
+//Respawns players inside the trigger of the same entity
+pub struct Hazard;
+impl Component for Hazard{
+	type Storage = HashMapStorage<Hazard>;
+}
+
+//A grid of tiles. The position of the entity is the top left corner of the first tile.
+//The tiles collide by the solids and triggers created by systems::ingame::TilemapColliders, where adjacent tiles are merged so that nothing snags on the edges between them
+//Nothing draws the tiles by itself: meshes built from parts() when the tilemap is created do not follow later edits or moves
+pub struct Tilemap{
+	pub tile_size: f64,
+	pub width    : usize,
+	pub height   : usize,
+	pub tiles    : Vec<u16>,      //Tile IDs, row by row from the top
+	pub types    : Vec<TileType>, //Collision type of every tile ID
+	pub material : Material,
+	pub dirty    : bool,          //Whether the colliders have to be recreated (the meshes drawing the tiles are not)
+}
+impl Tilemap{
+	pub fn new(tile_size: f64,width: usize,tiles: Vec<u16>,types: Vec<TileType>) -> Self{Tilemap{
+		tile_size: tile_size,
+		width    : width,
+		height   : if width==0{0}else{tiles.len() / width},
+		tiles    : tiles,
+		types    : types,
+		material : Material::NORMAL,
+		dirty    : true,
+	}}
+
+	//A tilemap from rows of characters: '#' solid, '-' one-way, '/' and '\' slopes, '^' hazard and anything else empty
+	pub fn from_rows(tile_size: f64,rows: &[&str]) -> Self{
+		let width = rows.iter().map(|row| row.chars().count()).max().unwrap_or(0);
+		let mut tiles = vec![0; width*rows.len()];
+		for (y,row) in rows.iter().enumerate(){
+			for (x,c) in row.chars().enumerate(){
+				tiles[x + y*width] = match c{
+					'#'  => 1,
+					'-'  => 2,
+					'/'  => 3,
+					'\\' => 4,
+					'^'  => 5,
+					_    => 0,
+				};
+			}
+		}
+		Self::new(tile_size,width,tiles,vec![
+			TileType::Empty,
+			TileType::Solid,
+			TileType::OneWay,
+			TileType::SlopeUp,
+			TileType::SlopeDown,
+			TileType::Hazard,
+		])
+	}
+
+	//Collision type of the tile. Outside the map and unknown tile IDs are empty
+	pub fn get(&self,x: usize,y: usize) -> TileType{
+		if x >= self.width || y >= self.height{
+			return TileType::Empty;
+		}
+		self.types.get(self.tiles[x + y*self.width] as usize).cloned().unwrap_or(TileType::Empty)
+	}
+
+	pub fn set(&mut self,x: usize,y: usize,id: u16){
+		if x < self.width && y < self.height{
+			self.tiles[x + y*self.width] = id;
+			self.dirty = true;
+		}
+	}
+
+	//The collision shapes of the tiles. Slopes are merged with the solid tiles on their high side in the same row, the other solid and hazard tiles into rectangles, and one-way tiles into rows
+	pub fn parts(&self) -> Vec<TilemapPart>{
+		let size = self.tile_size;
+		let mut parts = Vec::new();
+		let mut used  = vec![false; self.width*self.height];
+		let free = |used: &Vec<bool>,x: usize,y: usize,typ: TileType| !used[x + y*self.width] && self.get(x,y)==typ;
+
+		//A row rising from a slope up and falling to a slope down, with solid tiles between, is convex. This way there are no edges where the slopes meet the flat ground
+		for y in 0..self.height{
+			for x in 0..self.width{
+				let typ = self.get(x,y);
+				if used[x + y*self.width] || (typ!=TileType::SlopeUp && typ!=TileType::SlopeDown){
+					continue;
+				}
+
+				let (mut x1,mut x2) = (x,x+1);
+				if typ==TileType::SlopeUp{
+					while x2 < self.width && free(&used,x2,y,TileType::Solid){
+						x2+= 1;
+					}
+					if x2 < self.width && free(&used,x2,y,TileType::SlopeDown){
+						x2+= 1;
+					}
+				}else{
+					while x1 > 0 && free(&used,x1-1,y,TileType::Solid){
+						x1-= 1;
+					}
+				}
+				for xx in x1..x2{
+					used[xx + y*self.width] = true;
+				}
+
+				//The corners of the row in tiles. The top corner of a slope is on its high side
+				let top_left  = if self.get(x1,y)==TileType::SlopeUp{x1+1}else{x1};
+				let top_right = if self.get(x2-1,y)==TileType::SlopeDown{x2-1}else{x2};
+				let mut corners = vec![(x1,y+1),(x2,y+1),(top_right,y)];
+				if top_left!=top_right{
+					corners.push((top_left,y));
+				}
+
+				let offset = Vector2::new((x1 + x2) as f64 * size/2.0,(y as f64 + 0.5) * size);
+				parts.push(TilemapPart{
+					typ   : typ,
+					offset: offset,
+					shape : ShapeHandle2::new(ConvexHull::new(corners.into_iter()
+						.map(|(x,y)| Point2::new(x as f64 * size - offset[0],y as f64 * size - offset[1]))
+						.collect()
+					)),
+				});
+			}
+		}
+
+		for y in 0..self.height{
+			for x in 0..self.width{
+				let typ = self.get(x,y);
+				if used[x + y*self.width] || typ==TileType::Empty{
+					continue;
+				}
+
+				//Extend to the right, then downwards as long as the whole row matches
+				let mut x2 = x+1;
+				while x2 < self.width && free(&used,x2,y,typ){
+					x2+= 1;
+				}
+				let mut y2 = y+1;
+				if typ!=TileType::OneWay{
+					while y2 < self.height && (x..x2).all(|x| free(&used,x,y2,typ)){
+						y2+= 1;
+					}
+				}
+				for yy in y..y2{
+					for xx in x..x2{
+						used[xx + yy*self.width] = true;
+					}
+				}
+
+				let width = (x2 - x) as f64 * size;
+				parts.push(if typ==TileType::OneWay{
+					//Only the top quarter of the tile
+					TilemapPart{
+						typ   : typ,
+						offset: Vector2::new(x as f64 * size + width/2.0,y as f64 * size + size/8.0),
+						shape : ShapeHandle2::new(Cuboid::new(Vector2::new(width/2.0,size/8.0))),
+					}
+				}else{
+					let height = (y2 - y) as f64 * size;
+					TilemapPart{
+						typ   : typ,
+						offset: Vector2::new(x as f64 * size + width/2.0,y as f64 * size + height/2.0),
+						shape : ShapeHandle2::new(Cuboid::new(Vector2::new(width/2.0,height/2.0))),
+					}
+				});
+			}
+		}
+		parts
+	}
+}
+impl Component for Tilemap{
+	type Storage = HashMapStorage<Tilemap>;
+}
+
+//State machine of a character controller. Updated by the physics from what the character is touching, and by the controller from its actions
+pub struct Mover{
+	pub state         : MoverState,
+	pub previous_state: MoverState, //The state before the current one, for example to tell landings from a jump and from a fall apart
+	pub movement      : MoverMovement,
+	pub data          : MoverData,
+}
+impl Mover{
+	pub fn new() -> Self{Mover{
+		state         : MoverState::Falling,
+		previous_state: MoverState::Falling,
+		movement      : MoverMovement::Freely{direction: zero()},
+		data          : MoverData::new(),
+	}}
+
+	//Changes the state. The start time is only updated when changing to another kind of state
+	pub fn set_state(&mut self,state: MoverState,time: f64){
+		use std::mem::discriminant;
+
+		if discriminant(&state) != discriminant(&self.state){
+			self.previous_state        = self.state;
+			self.data.state_start_time = time;
+		}
+		self.state = state;
+	}
+
+	//Changes the movement. The start time is only updated when changing to another kind of movement
+	pub fn set_movement(&mut self,movement: MoverMovement,time: f64){
+		use std::mem::discriminant;
+
+		if discriminant(&movement) != discriminant(&self.movement){
+			self.data.movement_start_time = time;
+		}
+		self.movement = movement;
+	}
+
+	//Starts over falling, for example when respawning
+	pub fn reset(&mut self,time: f64){
+		*self = Mover::new();
+		self.data.state_start_time    = time;
+		self.data.movement_start_time = time;
+	}
+
+	//How long the current state has lasted
+	#[inline(always)]
+	pub fn state_duration(&self,time: f64) -> f64{
+		time - self.data.state_start_time
+	}
+}
+impl Component for Mover{
+	type Storage = HashMapStorage<Mover>;
+}
+
 pub struct Player{
-	pub id: u8,
-	//pub state: MoverState,
+	pub id          : u8,
+	pub spawn       : Vector2<f64>, //Where to respawn, for example when crushed
+	pub controls    : Controls, //What the player is asked to do in this frame
+	pub old_controls: Controls, //The controls from the previous frame
+	pub climbing    : bool,
+}
+impl Player{
+	pub fn new(id: u8,spawn: Vector2<f64>) -> Self{Player{
+		id          : id,
+		spawn       : spawn,
+		controls    : Controls::default(),
+		old_controls: Controls::default(),
+		climbing    : false,
+	}}
 }
 impl Component for Player{
 	type Storage = HashMapStorage<Player>;
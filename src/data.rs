@@ -1,6 +1,12 @@
 #![allow(dead_code)]
 
-use nalgebra::Vector2;
+use amethyst::ecs::Entity;
+use nalgebra::{Point2,Vector2};
+use ncollide::bounding_volume::AABB;
+use ncollide::shape::ShapeHandle2;
+use std::collections::{HashMap,HashSet};
+use std::error::Error;
+use std::path::Path;
 
 #[derive(Copy,Clone,PartialEq)]
 pub struct Camera{
@@ -26,32 +32,438 @@ impl Score{
 	}}
 }
 
+#[derive(Copy,Clone,PartialEq)]
+pub struct PhysicsTime{
+	pub step       : f64, //Duration of one physics step in seconds
+	pub max_steps  : u32, //Maximum number of steps in a single frame. The rest of the time is dropped when exceeding this
+	pub accumulator: f64, //Time not yet simulated. Less than `step` after each physics run
+	pub time       : f64, //Time simulated in total (seconds)
+}
+impl PhysicsTime{
+	pub fn new(step: f64) -> Self{PhysicsTime{
+		step       : step,
+		max_steps  : 8,
+		accumulator: 0.0,
+		time       : 0.0,
+	}}
+
+	//How far the simulation is between the previous and the current step (0.0 to 1.0). Used for interpolation when rendering
+	#[inline(always)]
+	pub fn alpha(&self) -> f64{
+		self.accumulator / self.step
+	}
+}
+
+//Values for how the game feels, loaded from a file so that they can be changed while the game is running
+#[derive(Copy,Clone,PartialEq,Deserialize)]
+#[serde(default)]
+pub struct Tuning{
+	pub gravity          : f64, //pixels/seconds^2
+	pub air_friction     : f64, //pixels/seconds^2
+	pub jump_velocity    : f64, //pixels/seconds
+	pub walk_acceleration: f64, //pixels/seconds^2
+	pub walk_speed       : f64, //pixels/seconds. Walking does not accelerate beyond this speed
+	pub wall_slide_speed : f64, //pixels/seconds. Maximum falling speed when holding towards a wall
+	pub wall_jump_side   : f64, //pixels/seconds. Speed away from the wall when jumping from it
+	pub wall_jump_up     : f64, //pixels/seconds. Upwards speed when jumping from a wall
+	pub climb_speed      : f64, //pixels/seconds
+	pub swim_stroke      : f64, //pixels/seconds. Upwards speed of a swimming stroke
+	pub swim_submerged   : f64, //How much of the player has to be in a fluid to swim (0.0 to 1.0)
+}
+impl Tuning{
+	pub const PATH: &'static str = "resources/tuning.yml";
+
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Self,Box<Error>>{
+		use std::fs::File;
+		use serde_yaml;
+
+		Ok(serde_yaml::from_reader(File::open(path)?)?)
+	}
+}
+impl Default for Tuning{
+	fn default() -> Self{Tuning{
+		gravity          : 600.0,
+		air_friction     : 20.0,
+		jump_velocity    : 420.0,
+		walk_acceleration: 1200.0,
+		walk_speed       : 100.0,
+		wall_slide_speed : 60.0,
+		wall_jump_side   : 200.0,
+		wall_jump_up     : 380.0,
+		climb_speed      : 80.0,
+		swim_stroke      : 180.0,
+		swim_submerged   : 0.5,
+	}}
+}
+
+//Uniform grid used to find the entities that possibly are colliding with each other
+pub struct Broadphase{
+	pub cell_size: f64,
+	cells  : HashMap<(i32,i32),Vec<Entity>>,
+	entries: HashMap<Entity,BroadphaseEntry>,
+	bounds : Option<((i32,i32),(i32,i32))>, //Range of the cells with entities in it, kept up to date by update and remove
+}
+struct BroadphaseEntry{
+	mins : (i32,i32),
+	maxs : (i32,i32),
+	fixed: bool,
+}
+impl Broadphase{
+	pub fn new(cell_size: f64) -> Self{Broadphase{
+		cell_size: cell_size,
+		cells    : HashMap::new(),
+		entries  : HashMap::new(),
+		bounds   : None,
+	}}
+
+	fn cell_range(&self,aabb: &AABB<Point2<f64>>) -> ((i32,i32),(i32,i32)){
+		let mins = aabb.mins();
+		let maxs = aabb.maxs();
+		(
+			((mins[0] / self.cell_size).floor() as i32,(mins[1] / self.cell_size).floor() as i32),
+			((maxs[0] / self.cell_size).floor() as i32,(maxs[1] / self.cell_size).floor() as i32),
+		)
+	}
+
+	//Inserts the entity or moves it to the cells overlapping the bounding box.
+	//Fixed entities are only inserted once and ignored in later updates.
+	pub fn update(&mut self,entity: Entity,aabb: &AABB<Point2<f64>>,fixed: bool){
+		let (mins,maxs) = self.cell_range(aabb);
+
+		if let Some(entry) = self.entries.get_mut(&entity){
+			if entry.fixed && fixed{
+				return;
+			}
+			entry.fixed = fixed;
+			if entry.mins==mins && entry.maxs==maxs{
+				return;
+			}
+		}
+
+		let old = self.unlink(entity);
+		for x in mins.0 .. maxs.0+1{
+			for y in mins.1 .. maxs.1+1{
+				self.cells.entry((x,y)).or_insert_with(Vec::new).push(entity);
+			}
+		}
+		self.entries.insert(entity,BroadphaseEntry{
+			mins : mins,
+			maxs : maxs,
+			fixed: fixed,
+		});
+		self.bounds = Some(match self.bounds{
+			Some((bounds_mins,bounds_maxs)) => (
+				(bounds_mins.0.min(mins.0),bounds_mins.1.min(mins.1)),
+				(bounds_maxs.0.max(maxs.0),bounds_maxs.1.max(maxs.1)),
+			),
+			None => (mins,maxs),
+		});
+		if let Some(old) = old{
+			self.shrink_bounds(old,Some((mins,maxs)));
+		}
+	}
+
+	//Whether the entity was inserted as fixed, so that its bounding box does not have to be computed again
+	pub fn is_fixed(&self,entity: Entity) -> bool{
+		self.entries.get(&entity).map_or(false,|entry| entry.fixed)
+	}
+
+	pub fn remove(&mut self,entity: Entity){
+		if let Some(old) = self.unlink(entity){
+			self.shrink_bounds(old,None);
+		}
+	}
+
+	//Removes the entity from its cells without updating the bounds, returning its old cell range
+	fn unlink(&mut self,entity: Entity) -> Option<((i32,i32),(i32,i32))>{
+		self.entries.remove(&entity).map(|entry|{
+			for x in entry.mins.0 .. entry.maxs.0+1{
+				for y in entry.mins.1 .. entry.maxs.1+1{
+					let empty = if let Some(cell) = self.cells.get_mut(&(x,y)){
+						cell.retain(|&e| e!=entity);
+						cell.is_empty()
+					}else{
+						false
+					};
+					if empty{
+						self.cells.remove(&(x,y));
+					}
+				}
+			}
+			(entry.mins,entry.maxs)
+		})
+	}
+
+	//Recomputes the bounds when an entry that was on one of their edges left it, which is the only way for them to shrink
+	fn shrink_bounds(&mut self,(old_mins,old_maxs): ((i32,i32),(i32,i32)),new: Option<((i32,i32),(i32,i32))>){
+		let (mins,maxs) = match self.bounds{
+			Some(bounds) => bounds,
+			None => return,
+		};
+		let left = |old: i32,edge: i32,new: Option<i32>| old==edge && new!=Some(edge);
+		let shrinks =
+			left(old_mins.0,mins.0,new.map(|(new_mins,_)| new_mins.0)) ||
+			left(old_mins.1,mins.1,new.map(|(new_mins,_)| new_mins.1)) ||
+			left(old_maxs.0,maxs.0,new.map(|(_,new_maxs)| new_maxs.0)) ||
+			left(old_maxs.1,maxs.1,new.map(|(_,new_maxs)| new_maxs.1));
+		if shrinks{
+			self.bounds = self.entries.values().fold(None,|bounds,entry| Some(match bounds{
+				Some((mins,maxs)) => (
+					(entry.mins.0.min(mins.0),entry.mins.1.min(mins.1)),
+					(entry.maxs.0.max(maxs.0),entry.maxs.1.max(maxs.1)),
+				),
+				None => (entry.mins,entry.maxs),
+			}));
+		}
+	}
+
+	//Removes every entity not satisfying the predicate
+	pub fn retain<F>(&mut self,mut f: F) where
+		F: FnMut(Entity) -> bool
+	{
+		let removed: Vec<Entity> = self.entries.keys().cloned().filter(|&e| !f(e)).collect();
+		for entity in removed{
+			self.remove(entity);
+		}
+	}
+
+	//The entities in a cell
+	pub fn cell(&self,x: i32,y: i32) -> &[Entity]{
+		self.cells.get(&(x,y)).map(|cell| &cell[..]).unwrap_or(&[])
+	}
+
+	//Bounding box of every cell with entities in it, or None when there are none
+	pub fn bounds(&self) -> Option<AABB<Point2<f64>>>{
+		self.bounds.map(|(mins,maxs)| AABB::new(
+			Point2::new(mins.0 as f64 * self.cell_size,mins.1 as f64 * self.cell_size),
+			Point2::new((maxs.0+1) as f64 * self.cell_size,(maxs.1+1) as f64 * self.cell_size),
+		))
+	}
+
+	//Every entity in the cells overlapping the bounding box, without duplicates
+	pub fn query(&self,aabb: &AABB<Point2<f64>>) -> Vec<Entity>{
+		let (mins,maxs) = self.cell_range(aabb);
+		let mut seen  = HashSet::new();
+		let mut found = Vec::new();
+		for x in mins.0 .. maxs.0+1{
+			for y in mins.1 .. maxs.1+1{
+				if let Some(cell) = self.cells.get(&(x,y)){
+					for &entity in cell{
+						if seen.insert(entity){
+							found.push(entity);
+						}
+					}
+				}
+			}
+		}
+		found
+	}
+}
+
+//A contact from the last physics step
+#[derive(Copy,Clone,PartialEq)]
+pub struct Contact{
+	pub other            : Entity,
+	pub normal           : Vector2<f64>, //Pointing from the checked object towards the other
+	pub depth            : f64,
+	pub relative_velocity: Vector2<f64>, //Velocity of the checked object relative to the other when the contact was found
+	pub impulse          : f64,          //Velocity (pixels/seconds) removed along the normal by the solver in the step. How hard the objects were pressed together
+}
+
+#[derive(Copy,Clone,Eq,PartialEq,Hash)]
+pub enum ContactState{
+	Started,
+	Persisted,
+	Ended,
+}
+
+#[derive(Copy,Clone,PartialEq)]
+pub struct CollisionEvent{
+	pub state            : ContactState,
+	pub entity           : Entity, //The checked object
+	pub other            : Entity,
+	pub normal           : Vector2<f64>, //Pointing from the entity towards the other
+	pub depth            : f64,
+	pub relative_velocity: Vector2<f64>,
+}
+
+#[derive(Copy,Clone,PartialEq)]
+pub struct TriggerEvent{
+	pub state  : ContactState, //Started when entering, persisted when staying and ended when leaving the trigger
+	pub trigger: Entity,
+	pub entity : Entity,
+}
+
+//A solid squeezed from opposite sides by solids that cannot be pushed away
+#[derive(Copy,Clone,PartialEq)]
+pub struct CrushEvent{
+	pub entity: Entity,
+	pub normal: Vector2<f64>, //Normal of one of the squeezing contacts, pointing from the entity towards it
+	pub depth : f64,          //Sum of the penetration depths of the squeezing contacts
+}
+
+//Events from the current frame. Cleared by the system producing them at the beginning of every frame, so only systems running after it will see every event
+pub struct Events<T>{
+	events: Vec<T>,
+}
+impl<T> Events<T>{
+	pub fn new() -> Self{Events{
+		events: Vec::new(),
+	}}
+
+	#[inline(always)]
+	pub fn push(&mut self,event: T){
+		self.events.push(event);
+	}
+
+	#[inline(always)]
+	pub fn clear(&mut self){
+		self.events.clear();
+	}
+
+	#[inline(always)]
+	pub fn iter(&self) -> ::std::slice::Iter<T>{
+		self.events.iter()
+	}
+
+	#[inline(always)]
+	pub fn is_empty(&self) -> bool{
+		self.events.is_empty()
+	}
+}
+
 /*#[derive(Copy,Clone,Eq,PartialEq,Hash)]
 pub enum CollisionType{
 	Static,
 	Dynamic,
 }*/
 
+#[derive(Copy,Clone,Eq,PartialEq,Hash)]
+pub enum PathMode{
+	PingPong, //Goes back through the waypoints when reaching the last one
+	Loop,     //Continues to the first waypoint when reaching the last one
+	Once,     //Stops at the last waypoint
+}
+
+#[derive(Copy,Clone,PartialEq)]
+pub enum PathTiming{
+	Speed(f64),    //pixels/seconds
+	Duration(f64), //seconds
+}
+
+#[derive(Copy,Clone,PartialEq)]
+pub struct Waypoint{
+	pub position: Vector2<f64>,
+	pub timing  : PathTiming, //How fast to move along the segment between this waypoint and the next one
+	pub wait    : f64,        //Time (seconds) to wait when arriving at this waypoint
+}
+
+//Collision layers of solids. Solids only collide when each one is in a layer of the other one's mask
+pub mod layer{
+	pub const TERRAIN   : u32 = 1 << 0;
+	pub const PLAYER    : u32 = 1 << 1;
+	pub const PROJECTILE: u32 = 1 << 2;
+	pub const DEBRIS    : u32 = 1 << 3;
+
+	pub const NONE: u32 = 0;
+	pub const ALL : u32 = !0;
+}
+
+//The buttons of a player. Filled from the input devices, or from a script when running headless
+#[derive(Copy,Clone,Eq,PartialEq,Hash,Default)]
+pub struct Controls{
+	pub left : bool,
+	pub right: bool,
+	pub up   : bool,
+	pub down : bool,
+	pub jump : bool,
+}
+
+//How the friction coefficients of two materials are combined. When the materials have different rules, the one declared last is used
+#[derive(Copy,Clone,Eq,PartialEq,Hash,Ord,PartialOrd)]
+pub enum FrictionCombine{
+	Average,
+	Min,
+	Multiply,
+	Max,
+}
+impl FrictionCombine{
+	pub fn apply(self,a: f64,b: f64) -> f64{
+		match self{
+			FrictionCombine::Average  => (a + b) / 2.0,
+			FrictionCombine::Min      => a.min(b),
+			FrictionCombine::Multiply => a * b,
+			FrictionCombine::Max      => a.max(b),
+		}
+	}
+}
+
+//Surface properties of a solid. The friction coefficients are multiplied by how hard the solids are pressed together, from gravity or anything else pushing them
+#[derive(Copy,Clone,PartialEq)]
+pub struct Material{
+	pub static_friction : f64, //Stops the sliding completely when it is slow enough
+	pub kinetic_friction: f64, //Slows down the sliding
+	pub combine         : FrictionCombine,
+}
+impl Material{
+	//Walking adds 10 pixels/seconds in a step while the default gravity presses with 5, so the friction coefficients have to stay below 2 for walking to be possible
+	pub const NORMAL: Material = Material{static_friction: 1.0,kinetic_friction: 0.9 ,combine: FrictionCombine::Average};
+	pub const ICE   : Material = Material{static_friction: 0.1,kinetic_friction: 0.05,combine: FrictionCombine::Min};
+	pub const MUD   : Material = Material{static_friction: 1.6,kinetic_friction: 1.4 ,combine: FrictionCombine::Max};
+
+	//The static and kinetic friction coefficients between two materials
+	pub fn combine(&self,other: &Material) -> (f64,f64){
+		let rule = self.combine.max(other.combine);
+		(
+			rule.apply(self.static_friction ,other.static_friction),
+			rule.apply(self.kinetic_friction,other.kinetic_friction),
+		)
+	}
+}
+
 #[derive(Copy,Clone,Eq,PartialEq,Hash)]
 pub enum SolidType{
 	Solid,
 	FallThrough,
 }
 
-/*#[derive(Copy,Clone,PartialEq)]
+//Collision type of a tile in a tilemap
+#[derive(Copy,Clone,Eq,PartialEq,Hash)]
+pub enum TileType{
+	Empty,
+	Solid,
+	OneWay,    //Fall-through platform at the top of the tile
+	SlopeUp,   //Rising to the right
+	SlopeDown, //Falling to the right
+	Hazard,    //Respawns players touching it
+}
+
+//A collision shape of a tilemap, covering one or multiple tiles of the same type
+pub struct TilemapPart{
+	pub typ   : TileType,
+	pub offset: Vector2<f64>, //Position of the shape relative to the tilemap
+	pub shape : ShapeHandle2<f64>,
+}
+
+//State of the character controller
+#[derive(Copy,Clone,PartialEq)]
 pub enum MoverState{
 	OnGround{normal: Vector2<f64>},
 	Ducking{normal: Vector2<f64>},
 	Falling,
 	Jumping,
+	Climbing,
+	Swimming,
 }
 
+//How the character is moving
 #[derive(Copy,Clone,PartialEq)]
 pub enum MoverMovement{
 	Freely{direction: Vector2<f64>},
-	Colliding{direction: Vector2<f64>,normal: Vector2<f64>},
+	Colliding{direction: Vector2<f64>,normal: Vector2<f64>}, //Touching a wall. The normal points away from it
 }
 
+//When the state and movement last changed, in the simulated time of PhysicsTime
 #[derive(Copy,Clone,PartialEq)]
 pub struct MoverData{
 	pub state_start_time   : f64,
@@ -62,4 +474,4 @@ impl MoverData{
 		state_start_time   : 0.0,
 		movement_start_time: 0.0,
 	}}
-}*/
+}
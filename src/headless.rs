@@ -0,0 +1,547 @@
+//Runs the game logic without a window. Used by the tests for checking the physics with scripted inputs
+#![allow(dead_code)]
+
+use amethyst::ecs::{Dispatcher,DispatcherBuilder,Entity,World};
+use amethyst::timing::Time;
+use nalgebra::Vector2;
+use ncollide::shape::{Cuboid,ShapeHandle2};
+use std::time::Duration;
+
+use *;
+
+//A world with the player and physics systems, advanced one physics step at a time
+pub struct Simulation{
+	pub world : World,
+	pub steps : u32, //Number of steps run
+	dispatcher: Dispatcher<'static,'static>,
+}
+impl Simulation{
+	pub fn new() -> Self{
+		use systems::ingame::{Physics,PlayerInput,PlayerRespawn,TilemapColliders};
+
+		let mut world = World::new();
+		world.register::<components::Solid>();
+		world.register::<components::Player>();
+		world.register::<components::Position>();
+		world.register::<components::CollisionCache>();
+		world.register::<components::GroundContact>();
+		world.register::<components::WallContact>();
+		world.register::<components::Mover>();
+		world.register::<components::Trigger>();
+		world.register::<components::PathMover>();
+		world.register::<components::GravityZone>();
+		world.register::<components::Climbable>();
+		world.register::<components::Fluid>();
+		world.register::<components::Hazard>();
+		world.register::<components::Tilemap>();
+
+		//Every frame is exactly one physics step. Rounded up so that the accumulator never falls short of a step
+		let mut time = Time::default();
+		time.delta_time = Duration::new(0,(Physics::STEP * 1.0e9).ceil() as u32);
+		world.add_resource(time);
+
+		//The tuning file is not used so that the results do not change when it is edited
+		world.add_resource(data::Tuning::default());
+		world.add_resource(data::PhysicsTime::new(Physics::STEP));
+		world.add_resource(data::Broadphase::new(64.0));
+		world.add_resource(data::Events::<data::CollisionEvent>::new());
+		world.add_resource(data::Events::<data::TriggerEvent>::new());
+		world.add_resource(data::Events::<data::CrushEvent>::new());
+
+		Simulation{
+			world     : world,
+			steps     : 0,
+			dispatcher: DispatcherBuilder::new()
+				.add(PlayerInput,"input_system",&[])
+				.add(TilemapColliders::new(),"tilemap_system",&[])
+				.add(Physics::new(),"physics_system",&["input_system","tilemap_system"])
+				.add(PlayerRespawn,"respawn_system",&["physics_system"])
+				.build(),
+		}
+	}
+
+	//A static rectangle
+	pub fn add_block(&mut self,position: Vector2<f64>,half_extents: Vector2<f64>,material: data::Material) -> Entity{
+		self.world.create_entity()
+			.with(components::Position(position))
+			.with(components::Solid::new(
+				data::SolidType::Solid,
+				false,
+				false,
+				material,
+				ShapeHandle2::new(Cuboid::new(half_extents)),
+			))
+			.build()
+	}
+
+	//A rectangle following the waypoints, starting at the first one
+	pub fn add_platform(&mut self,half_extents: Vector2<f64>,waypoints: Vec<data::Waypoint>,mode: data::PathMode) -> Entity{
+		let position = waypoints[0].position;
+		self.world.create_entity()
+			.with(components::Position(position))
+			.with(components::Solid::new(
+				data::SolidType::Solid,
+				false,
+				false,
+				data::Material::NORMAL,
+				ShapeHandle2::new(Cuboid::new(half_extents)),
+			))
+			.with(components::PathMover::new(waypoints,mode))
+			.build()
+	}
+
+	//A zone that players can climb in
+	pub fn add_climbable(&mut self,position: Vector2<f64>,half_extents: Vector2<f64>) -> Entity{
+		self.world.create_entity()
+			.with(components::Position(position))
+			.with(components::Trigger::new(ShapeHandle2::new(Cuboid::new(half_extents)),data::layer::PLAYER))
+			.with(components::Climbable)
+			.build()
+	}
+
+	//A fluid with the buoyancy and drag
+	pub fn add_fluid(&mut self,position: Vector2<f64>,half_extents: Vector2<f64>,buoyancy: f64,drag: f64) -> Entity{
+		self.world.create_entity()
+			.with(components::Position(position))
+			.with(components::Trigger::new(ShapeHandle2::new(Cuboid::new(half_extents)),data::layer::ALL))
+			.with(components::Fluid{
+				buoyancy: buoyancy,
+				drag    : drag,
+			})
+			.build()
+	}
+
+	//A tilemap from rows of characters (see components::Tilemap::from_rows)
+	pub fn add_tilemap(&mut self,position: Vector2<f64>,tile_size: f64,rows: &[&str]) -> Entity{
+		self.world.create_entity()
+			.with(components::Position(position))
+			.with(components::Tilemap::from_rows(tile_size,rows))
+			.build()
+	}
+
+	//A player like the ones in the game
+	pub fn add_player(&mut self,id: u8,position: Vector2<f64>) -> Entity{
+		self.world.create_entity()
+			.with(components::Player::new(id,position))
+			.with(components::Position(position))
+			.with(components::CollisionCache::new())
+			.with(components::GroundContact::new())
+			.with(components::WallContact::new())
+			.with(components::Mover::new())
+			.with(components::Solid{
+				layer: data::layer::PLAYER,
+				..components::Solid::new(
+					data::SolidType::Solid,
+					true,
+					true,
+					data::Material::NORMAL,
+					ShapeHandle2::new(Cuboid::new(Vector2::new(16.0,32.0))),
+				)
+			})
+			.build()
+	}
+
+	pub fn step(&mut self){
+		self.dispatcher.dispatch(&mut self.world.res);
+		self.world.maintain();
+		self.steps+= 1;
+	}
+
+	//Runs the steps, letting `script` set the controls before every step
+	pub fn run<F>(&mut self,steps: u32,mut script: F) where
+		F: FnMut(u32,&mut Simulation)
+	{
+		for _ in 0..steps{
+			let step = self.steps;
+			script(step,self);
+			self.step();
+		}
+	}
+
+	pub fn set_controls(&mut self,player: Entity,controls: data::Controls){
+		if let Some(player) = self.world.write::<components::Player>().get_mut(player){
+			player.controls = controls;
+		}
+	}
+
+	pub fn position(&self,entity: Entity) -> Vector2<f64>{
+		self.world.read::<components::Position>().get(entity).map_or(Vector2::new(0.0,0.0),|&components::Position(position)| position)
+	}
+
+	pub fn set_velocity(&mut self,entity: Entity,velocity: Vector2<f64>){
+		if let Some(solid) = self.world.write::<components::Solid>().get_mut(entity){
+			solid.velocity = velocity;
+		}
+	}
+
+	pub fn velocity(&self,entity: Entity) -> Vector2<f64>{
+		self.world.read::<components::Solid>().get(entity).map_or(Vector2::new(0.0,0.0),|solid| solid.velocity)
+	}
+
+	pub fn mover(&self,entity: Entity) -> Option<(data::MoverState,data::MoverData)>{
+		self.world.read::<components::Mover>().get(entity).map(|mover| (mover.state,mover.data))
+	}
+
+	//Simulated time in seconds
+	pub fn time(&self) -> f64{
+		self.world.read_resource::<data::PhysicsTime>().time
+	}
+
+	pub fn is_grounded(&self,entity: Entity) -> bool{
+		self.world.read::<components::GroundContact>().get(entity).map_or(false,|ground| ground.is_grounded())
+	}
+}
+
+//The floor used in the tests. Its top is at y = -16, so a player standing on it is at y = -48
+const FLOOR_TOP: f64 = -16.0;
+const PLAYER_HALF_HEIGHT: f64 = 32.0;
+
+fn floor(sim: &mut Simulation) -> Entity{
+	sim.add_block(Vector2::new(0.0,0.0),Vector2::new(600.0,16.0),data::Material::NORMAL)
+}
+
+#[test]
+fn rest_on_floor(){
+	let mut sim = Simulation::new();
+	floor(&mut sim);
+	let player = sim.add_player(0,Vector2::new(0.0,-100.0));
+	sim.run(240,|_,_|{});
+
+	let position = sim.position(player);
+	let velocity = sim.velocity(player);
+	assert!((position[1] - (FLOOR_TOP - PLAYER_HALF_HEIGHT)).abs() < 0.5,"Not resting on the floor: y = {}",position[1]);
+	assert!(velocity.norm() < 1.0,"Still moving: velocity = ({},{})",velocity[0],velocity[1]);
+	assert!(sim.is_grounded(player),"Not grounded");
+}
+
+#[test]
+fn jump(){
+	let mut sim = Simulation::new();
+	floor(&mut sim);
+	let player = sim.add_player(0,Vector2::new(0.0,FLOOR_TOP - PLAYER_HALF_HEIGHT));
+	sim.run(30,|_,_|{});
+
+	//Press jump for one step and find the highest point of the jump
+	let start = sim.position(player)[1];
+	let mut top = start;
+	sim.run(240,|step,sim|{
+		sim.set_controls(player,data::Controls{jump: step==30,..data::Controls::default()});
+		top = top.min(sim.position(player)[1]);
+	});
+
+	//The height without air friction is jump_velocity^2 / (2*gravity) = 147
+	let height = start - top;
+	assert!(height > 125.0 && height < 150.0,"Jump height is {}",height);
+	assert!(sim.is_grounded(player),"Not landed after the jump");
+	assert!((sim.position(player)[1] - start).abs() < 0.5,"Landed at y = {} instead of {}",sim.position(player)[1],start);
+}
+
+#[test]
+fn walk(){
+	let mut sim = Simulation::new();
+	floor(&mut sim);
+	let player = sim.add_player(0,Vector2::new(0.0,FLOOR_TOP - PLAYER_HALF_HEIGHT));
+	sim.run(240,|_,sim| sim.set_controls(player,data::Controls{right: true,..data::Controls::default()}));
+
+	let speed = data::Tuning::default().walk_speed;
+	let velocity = sim.velocity(player);
+	assert!(velocity[0] > speed*0.5 && velocity[0] < speed*1.1,"Walking at {} instead of about {}",velocity[0],speed);
+	assert!(velocity[1].abs() < 1.0,"Moving vertically at {} while walking",velocity[1]);
+}
+
+#[test]
+fn friction(){
+	let mut sim = Simulation::new();
+	floor(&mut sim);
+	let player = sim.add_player(0,Vector2::new(0.0,FLOOR_TOP - PLAYER_HALF_HEIGHT));
+	sim.run(30,|_,_|{});
+	sim.set_velocity(player,Vector2::new(200.0,0.0));
+
+	//Slides to a stop. The deceleration is the kinetic friction times the gravity and the air friction: 200^2 / (2*(0.9*600 + 20)) = 36
+	let start = sim.position(player)[0];
+	sim.run(120,|_,_|{});
+	let distance = sim.position(player)[0] - start;
+	assert!(sim.velocity(player).norm() < 1.0,"Still sliding at {}",sim.velocity(player)[0]);
+	assert!(distance > 25.0 && distance < 45.0,"Slid {} pixels",distance);
+}
+
+//Landing from a running jump only slows down by the usual friction instead of stopping
+#[test]
+fn running_jump(){
+	let mut sim = Simulation::new();
+	floor(&mut sim);
+	let player = sim.add_player(0,Vector2::new(0.0,FLOOR_TOP - PLAYER_HALF_HEIGHT));
+	sim.run(120,|_,sim| sim.set_controls(player,data::Controls{right: true,..data::Controls::default()}));
+	sim.run(1,|_,sim| sim.set_controls(player,data::Controls{right: true,jump: true,..data::Controls::default()}));
+	sim.set_controls(player,data::Controls::default());
+
+	//The speed in the last step in the air and in the first one on the ground
+	let mut falling = 0.0;
+	let mut landed  = None;
+	sim.run(240,|_,sim|{
+		if landed.is_none(){
+			if sim.is_grounded(player){
+				landed = Some(sim.velocity(player)[0]);
+			}else{
+				falling = sim.velocity(player)[0];
+			}
+		}
+	});
+	assert!(falling > 50.0,"Jumped forwards at {}",falling);
+	assert!(landed.is_some(),"Not landed after the jump");
+	assert!(landed.unwrap_or(0.0) > falling*0.8,"Slowed down from {} to {} when landing",falling,landed.unwrap_or(0.0));
+}
+
+//Mud is slow to walk on, but does not hold the player in place
+#[test]
+fn walk_on_mud(){
+	let mut sim = Simulation::new();
+	sim.add_block(Vector2::new(0.0,0.0),Vector2::new(600.0,16.0),data::Material::MUD);
+	let player = sim.add_player(0,Vector2::new(0.0,FLOOR_TOP - PLAYER_HALF_HEIGHT));
+	sim.run(30,|_,_|{});
+
+	let start = sim.position(player)[0];
+	sim.run(240,|_,sim| sim.set_controls(player,data::Controls{right: true,..data::Controls::default()}));
+	let speed  = data::Tuning::default().walk_speed;
+	let walked = sim.position(player)[0] - start;
+	assert!(sim.velocity(player)[0] > speed*0.5,"Walking at {} on mud",sim.velocity(player)[0]);
+	assert!(walked > speed,"Only walked {} pixels on mud in 2 seconds",walked);
+}
+
+//Standing on a conveyor belt moves along with its surface
+#[test]
+fn conveyor(){
+	let mut sim = Simulation::new();
+	let belt = floor(&mut sim);
+	{
+		let mut solids = sim.world.write::<components::Solid>();
+		if let Some(solid) = solids.get_mut(belt){
+			solid.surface_velocity = 80.0;
+		}
+	}
+	let player = sim.add_player(0,Vector2::new(0.0,FLOOR_TOP - PLAYER_HALF_HEIGHT));
+	sim.run(240,|_,_|{});
+
+	let velocity = sim.velocity(player);
+	assert!(velocity[0] > 60.0 && velocity[0] <= 80.0,"Moving at {} on the conveyor belt",velocity[0]);
+	assert!(sim.position(player)[0] > 100.0,"Only moved to x = {}",sim.position(player)[0]);
+}
+
+#[test]
+fn ride_platform(){
+	let mut sim = Simulation::new();
+	let platform = sim.add_platform(
+		Vector2::new(100.0,8.0),
+		vec![
+			data::Waypoint{position: Vector2::new(  0.0,0.0),timing: data::PathTiming::Speed(40.0),wait: 0.0},
+			data::Waypoint{position: Vector2::new(400.0,0.0),timing: data::PathTiming::Speed(40.0),wait: 0.0},
+		],
+		data::PathMode::PingPong
+	);
+	let player = sim.add_player(0,Vector2::new(0.0,-60.0));
+	sim.run(60,|_,_|{});
+	assert!(sim.is_grounded(player),"Not landed on the platform");
+
+	//Stays at the same place on the platform while it moves
+	let offset = sim.position(player) - sim.position(platform);
+	sim.run(240,|_,_|{});
+	let new_offset = sim.position(player) - sim.position(platform);
+	assert!((new_offset - offset).norm() < 2.0,"Moved ({},{}) relative to the platform",new_offset[0] - offset[0],new_offset[1] - offset[1]);
+	assert!(sim.position(platform)[0] > 80.0,"The platform only moved to x = {}",sim.position(platform)[0]);
+}
+
+//Holding towards a wall slows down the fall, and jumping launches away from it
+#[test]
+fn wall_jump(){
+	let mut sim = Simulation::new();
+	sim.add_block(Vector2::new(0.0,0.0),Vector2::new(16.0,400.0),data::Material::NORMAL);
+	let player = sim.add_player(0,Vector2::new(32.0,-300.0));
+	let tuning = data::Tuning::default();
+
+	//Player 0 holding left is pressed against the wall on its left
+	sim.run(120,|_,sim| sim.set_controls(player,data::Controls{left: true,..data::Controls::default()}));
+	let velocity = sim.velocity(player);
+	assert!(sim.world.read::<components::WallContact>().get(player).map_or(false,|wall| wall.is_touching()),"Not touching the wall");
+	assert!(velocity[1] <= tuning.wall_slide_speed + 20.0,"Falling at {} while sliding",velocity[1]);
+
+	sim.run(1,|_,sim| sim.set_controls(player,data::Controls{left: true,jump: true,..data::Controls::default()}));
+	sim.run(1,|_,sim| sim.set_controls(player,data::Controls::default()));
+	let velocity = sim.velocity(player);
+	assert!(velocity[0] > tuning.wall_jump_side*0.5,"Jumped sideways at {}",velocity[0]);
+	assert!(velocity[1] < -tuning.wall_jump_up*0.5,"Jumped upwards at {}",-velocity[1]);
+}
+
+//Other players are not walls, so holding towards one in the air does not slow down the fall
+#[test]
+fn no_wall_slide_on_player(){
+	let mut sim = Simulation::new();
+	sim.add_player(1,Vector2::new(0.0,-300.0));
+	let player = sim.add_player(0,Vector2::new(32.0,-300.0));
+	let tuning = data::Tuning::default();
+
+	sim.run(60,|_,sim| sim.set_controls(player,data::Controls{left: true,..data::Controls::default()}));
+	assert!(!sim.world.read::<components::WallContact>().get(player).map_or(false,|wall| wall.is_touching()),"Touching another player as a wall");
+	assert!(sim.velocity(player)[1] > tuning.wall_slide_speed + 20.0,"Falling at {} next to another player",sim.velocity(player)[1]);
+}
+
+//Climbing moves at the climbing speed without gravity, and jumping lets go
+#[test]
+fn climb(){
+	let mut sim = Simulation::new();
+	floor(&mut sim);
+	sim.add_climbable(Vector2::new(0.0,-200.0),Vector2::new(16.0,184.0));
+	let player = sim.add_player(0,Vector2::new(0.0,FLOOR_TOP - PLAYER_HALF_HEIGHT));
+	let tuning = data::Tuning::default();
+	sim.run(10,|_,_|{});
+
+	let start = sim.position(player)[1];
+	sim.run(60,|_,sim| sim.set_controls(player,data::Controls{up: true,..data::Controls::default()}));
+	let climbed = start - sim.position(player)[1];
+	assert!(climbed > tuning.climb_speed*0.4 && climbed < tuning.climb_speed*0.55,"Climbed {} pixels in 0.5 seconds",climbed);
+
+	//Stays in place when letting go of the buttons
+	let position = sim.position(player);
+	sim.run(60,|_,sim| sim.set_controls(player,data::Controls::default()));
+	assert!((sim.position(player) - position).norm() < 1.0,"Moved {} pixels while holding on",(sim.position(player) - position).norm());
+
+	sim.run(1,|_,sim| sim.set_controls(player,data::Controls{jump: true,..data::Controls::default()}));
+	assert!(!sim.world.read::<components::Player>().get(player).map_or(true,|player| player.climbing),"Still climbing after jumping");
+	assert!(sim.velocity(player)[1] < -tuning.jump_velocity*0.5,"Jumped at {}",-sim.velocity(player)[1]);
+}
+
+//Rises to the surface of water and floats, and swimming strokes can be repeated
+#[test]
+fn swim(){
+	let mut sim = Simulation::new();
+	floor(&mut sim);
+	sim.add_fluid(Vector2::new(0.0,-200.0),Vector2::new(200.0,184.0),1.2,2.0);
+	let player = sim.add_player(0,Vector2::new(0.0,-100.0));
+	let tuning = data::Tuning::default();
+	sim.run(1200,|_,_|{});
+
+	//Floats with 1/1.2 of the shape submerged
+	let submerged = sim.world.read::<components::Solid>().get(player).map_or(0.0,|solid| solid.submerged);
+	assert!(submerged > 0.7 && submerged < 0.95,"Floating with {} submerged",submerged);
+	assert!(sim.velocity(player).norm() < 20.0,"Still moving at {} while floating",sim.velocity(player).norm());
+
+	for _ in 0..2{
+		sim.run(1,|_,sim| sim.set_controls(player,data::Controls{jump: true,..data::Controls::default()}));
+		assert!(sim.velocity(player)[1] < -tuning.swim_stroke*0.5,"Stroke upwards at {}",-sim.velocity(player)[1]);
+		sim.run(1,|_,sim| sim.set_controls(player,data::Controls::default()));
+	}
+}
+
+//Walking over many tiles does not snag on the edges between them, and hazards respawn
+#[test]
+fn tilemap(){
+	let mut sim = Simulation::new();
+	let map = sim.add_tilemap(Vector2::new(-160.0,-32.0),16.0,&[
+		"                    ^^",
+		"######################",
+		"######################",
+	]);
+	let player = sim.add_player(0,Vector2::new(-140.0,-16.0 - PLAYER_HALF_HEIGHT));
+	sim.run(1,|_,_|{});
+
+	//The solid tiles are one rectangle
+	let parts = sim.world.read::<components::Tilemap>().get(map).map_or(0,|tilemap| tilemap.parts().len());
+	assert!(parts==2,"{} collision parts instead of 2",parts);
+
+	let start = sim.position(player)[0];
+	sim.run(240,|_,sim| sim.set_controls(player,data::Controls{right: true,..data::Controls::default()}));
+	let walked = sim.position(player)[0] - start;
+	assert!(walked > 150.0,"Only walked {} pixels",walked);
+	assert!((sim.position(player)[1] - (-16.0 - PLAYER_HALF_HEIGHT)).abs() < 0.5,"Not on the tiles: y = {}",sim.position(player)[1]);
+
+	//Walks into the hazard at the end and is respawned at the start
+	sim.run(180,|_,sim| sim.set_controls(player,data::Controls{right: true,..data::Controls::default()}));
+	assert!(sim.position(player)[0] < 100.0,"Not respawned: x = {}",sim.position(player)[0]);
+}
+
+//The controller state follows falling, landing, ducking and jumping, and is timestamped when it changes
+#[test]
+fn mover_states(){
+	fn is_on_ground(state: data::MoverState) -> bool{
+		match state{data::MoverState::OnGround{..} => true,_ => false}
+	}
+	fn is_ducking(state: data::MoverState) -> bool{
+		match state{data::MoverState::Ducking{..} => true,_ => false}
+	}
+
+	let mut sim = Simulation::new();
+	floor(&mut sim);
+	let player = sim.add_player(0,Vector2::new(0.0,-100.0));
+	sim.run(2,|_,_|{});
+	assert!(sim.mover(player).map_or(false,|(state,_)| state==data::MoverState::Falling),"Not falling at the start");
+
+	//Lands and remembers when
+	let mut landed = None;
+	sim.run(120,|_,sim|{
+		if landed.is_none() && sim.mover(player).map_or(false,|(state,_)| is_on_ground(state)){
+			landed = Some(sim.time());
+		}
+	});
+	let (state,data) = sim.mover(player).unwrap();
+	assert!(is_on_ground(state),"Not on the ground after falling");
+	assert!(landed.map_or(false,|time| (data.state_start_time - time).abs() < 1.0e-9),"Landing timestamped at {} instead of {:?}",data.state_start_time,landed);
+
+	sim.run(10,|_,sim| sim.set_controls(player,data::Controls{down: true,..data::Controls::default()}));
+	assert!(sim.mover(player).map_or(false,|(state,_)| is_ducking(state)),"Not ducking while holding down");
+	sim.run(1,|_,sim| sim.set_controls(player,data::Controls::default()));
+
+	sim.run(1,|_,sim| sim.set_controls(player,data::Controls{jump: true,..data::Controls::default()}));
+	let (state,data) = sim.mover(player).unwrap();
+	assert!(state==data::MoverState::Jumping,"Not jumping after jumping");
+	assert!(sim.time() - data.state_start_time <= systems::ingame::Physics::STEP + 1.0e-9,"Jump timestamped at {} at the time {}",data.state_start_time,sim.time());
+}
+
+//A player carried into a ceiling by a platform is crushed and respawned
+#[test]
+fn crush(){
+	let mut sim = Simulation::new();
+	sim.add_block(Vector2::new(0.0,-200.0),Vector2::new(100.0,16.0),data::Material::NORMAL);
+	sim.add_platform(
+		Vector2::new(50.0,8.0),
+		vec![
+			data::Waypoint{position: Vector2::new(0.0,   0.0),timing: data::PathTiming::Speed(60.0),wait: 0.0},
+			data::Waypoint{position: Vector2::new(0.0,-150.0),timing: data::PathTiming::Speed(60.0),wait: 0.0},
+		],
+		data::PathMode::Once
+	);
+	let player = sim.add_player(0,Vector2::new(0.0,-8.0 - PLAYER_HALF_HEIGHT));
+	{
+		let mut players = sim.world.write::<components::Player>();
+		if let Some(player) = players.get_mut(player){
+			player.spawn = Vector2::new(400.0,-100.0);
+		}
+	}
+
+	let mut crushed = false;
+	sim.run(360,|_,sim|{
+		crushed = crushed || !sim.world.read_resource::<data::Events<data::CrushEvent>>().is_empty();
+	});
+	assert!(crushed,"Not crushed");
+	assert!(sim.position(player)[0] > 200.0,"Not respawned: x = {}",sim.position(player)[0]);
+}
+
+//The same inputs should always give the same results
+#[test]
+fn deterministic(){
+	fn simulate() -> Vec<Vector2<f64>>{
+		let mut sim = Simulation::new();
+		floor(&mut sim);
+		let players = vec![
+			sim.add_player(0,Vector2::new(-50.0,-100.0)),
+			sim.add_player(1,Vector2::new( 50.0,-150.0)),
+		];
+		sim.run(600,|step,sim|{
+			let a = players[0];
+			let b = players[1];
+			sim.set_controls(a,data::Controls{right: step%200 < 120,jump: step%90 < 5,..data::Controls::default()});
+			sim.set_controls(b,data::Controls{left : step%150 < 100,jump: step%70 < 5,..data::Controls::default()});
+		});
+		players.iter().flat_map(|&player| vec![sim.position(player),sim.velocity(player)]).collect()
+	}
+
+	assert!(simulate()==simulate(),"Different results from the same inputs");
+}
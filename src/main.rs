@@ -3,9 +3,14 @@ extern crate amethyst;
 extern crate nalgebra;
 extern crate ncollide;
 extern crate futures;
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+extern crate serde_yaml;
 
 mod components;
 mod data;
+#[cfg(test)] mod headless;
+mod query;
 mod util;
 mod states;
 mod systems;
@@ -26,10 +31,24 @@ fn main(){
 		.register::<components::Player>()
 		.register::<components::Position>()
 		.register::<components::CollisionCache>()
-		.with::<systems::ingame::PlayerInput>(systems::ingame::PlayerInput, "input_system", &[])
-		.with::<systems::ingame::Physics>(systems::ingame::Physics::new(), "physics_system", &[])
-		.with::<systems::ingame::Render>(systems::ingame::Render, "render_system", &[])
-		.with::<TransformSystem>(TransformSystem::new(), "transform_system", &["physics_system"])
+		.register::<components::GroundContact>()
+		.register::<components::WallContact>()
+		.register::<components::Mover>()
+		.register::<components::Trigger>()
+		.register::<components::PathMover>()
+		.register::<components::GravityZone>()
+		.register::<components::Climbable>()
+		.register::<components::Fluid>()
+		.register::<components::Hazard>()
+		.register::<components::Tilemap>()
+		.with::<systems::ingame::TuningReload>(systems::ingame::TuningReload::new(data::Tuning::PATH), "tuning_system", &[])
+		.with::<systems::ingame::PlayerControls>(systems::ingame::PlayerControls, "controls_system", &[])
+		.with::<systems::ingame::PlayerInput>(systems::ingame::PlayerInput, "input_system", &["tuning_system","controls_system"])
+		.with::<systems::ingame::TilemapColliders>(systems::ingame::TilemapColliders::new(), "tilemap_system", &[])
+		.with::<systems::ingame::Physics>(systems::ingame::Physics::new(), "physics_system", &["tuning_system","input_system","tilemap_system"])
+		.with::<systems::ingame::PlayerRespawn>(systems::ingame::PlayerRespawn, "respawn_system", &["physics_system"])
+		.with::<systems::ingame::Render>(systems::ingame::Render, "render_system", &["physics_system","respawn_system"])
+		.with::<TransformSystem>(TransformSystem::new(), "transform_system", &["render_system"])
 		.with_store("resources", Directory::new("resources"))
 		.with_bundle(
 			RenderBundle::new(Pipeline::build().with_stage(
@@ -0,0 +1,316 @@
+#![allow(dead_code)]
+
+use amethyst::ecs::{self,Entity};
+use nalgebra::{Isometry2,Point2,Vector2,zero};
+use ncollide::bounding_volume::{AABB,BoundingVolume};
+use ncollide::query::{Ray,RayCast};
+use ncollide::shape::ShapeHandle2;
+use std::ops::Deref;
+
+use *;
+
+//Which solids a query should consider
+#[derive(Copy,Clone,PartialEq)]
+pub struct QueryFilter{
+	pub mask  : u32,            //The collision layers to find (see data::layer)
+	pub ignore: Option<Entity>, //Never found, for example the entity asking. Solids ignoring it are not found either
+}
+impl QueryFilter{
+	pub fn new(mask: u32) -> Self{QueryFilter{
+		mask  : mask,
+		ignore: None,
+	}}
+
+	#[inline(always)]
+	fn accepts(&self,entity: Entity,solid: &components::Solid) -> bool{
+		(self.mask & solid.layer)!=0 && self.ignore!=Some(entity) && (self.ignore.is_none() || solid.ignore!=self.ignore)
+	}
+}
+
+#[derive(Copy,Clone,PartialEq)]
+pub struct Hit{
+	pub entity: Entity,
+	pub toi   : f64,          //How far along the ray or motion the hit is (in multiples of the direction/motion)
+	pub point : Vector2<f64>,
+	pub normal: Vector2<f64>, //Surface normal of the hit solid
+}
+
+//Questions about the solids in the world, using the broadphase from the physics
+pub struct WorldQuery<'q,'a: 'q>{
+	pub positions : &'q ecs::ReadStorage<'a,components::Position>,
+	pub solids    : &'q ecs::ReadStorage<'a,components::Solid>,
+	pub broadphase: &'q data::Broadphase,
+}
+impl<'q,'a> WorldQuery<'q,'a>{
+	pub fn new(
+		positions : &'q ecs::ReadStorage<'a,components::Position>,
+		solids    : &'q ecs::ReadStorage<'a,components::Solid>,
+		broadphase: &'q data::Broadphase,
+	) -> Self{WorldQuery{
+		positions : positions,
+		solids    : solids,
+		broadphase: broadphase,
+	}}
+
+	//The solids near the bounding box that the filter accepts
+	fn candidates<'s>(&'s self,aabb: &AABB<Point2<f64>>,filter: &QueryFilter) -> Vec<(Entity,Vector2<f64>,&'s components::Solid)>{
+		self.broadphase.query(aabb).into_iter()
+			.filter_map(|entity| match (self.positions.get(entity),self.solids.get(entity)){
+				(Some(&components::Position(position)),Some(solid)) if filter.accepts(entity,solid) => Some((entity,position,solid)),
+				_ => None,
+			})
+			.collect()
+	}
+
+	//The first solid hit by the ray from `origin` in `direction`, at most `max_toi` times the direction away.
+	//Walks through the cells of the broadphase along the ray, so only the cells that the ray passes before the hit are checked
+	pub fn raycast(&self,origin: Vector2<f64>,direction: Vector2<f64>,max_toi: f64,filter: &QueryFilter) -> Option<Hit>{
+		use std::f64::INFINITY;
+
+		//Nothing can be hit outside of the cells with solids, which also limits infinite rays
+		let (enter,exit) = match self.broadphase.bounds().and_then(|bounds| util::ray_aabb(origin,direction,&bounds)){
+			Some(range) => range,
+			None => return None,
+		};
+		let start   = enter.max(0.0);
+		let max_toi = max_toi.min(exit);
+		if start > max_toi{
+			return None;
+		}
+
+		let ray  = Ray::new(Point2::new(origin[0],origin[1]),direction);
+		let size = self.broadphase.cell_size;
+		let start_point = origin + direction * start;
+		let mut cell = ((start_point[0] / size).floor() as i32,(start_point[1] / size).floor() as i32);
+
+		//How far along the ray the next cell boundary is on each axis, and the distance between the boundaries
+		let boundary = |cell: i32,origin: f64,direction: f64| if direction > 0.0{
+			((cell+1) as f64 * size - origin) / direction
+		}else if direction < 0.0{
+			(cell as f64 * size - origin) / direction
+		}else{
+			INFINITY
+		};
+		let mut next = (boundary(cell.0,origin[0],direction[0]),boundary(cell.1,origin[1],direction[1]));
+		let delta = (size / direction[0].abs(),size / direction[1].abs());
+		let step  = (if direction[0] > 0.0{1}else{-1},if direction[1] > 0.0{1}else{-1});
+
+		let mut checked: Vec<Entity> = Vec::new();
+		let mut first: Option<Hit> = None;
+		loop{
+			for &entity in self.broadphase.cell(cell.0,cell.1){
+				if checked.contains(&entity){
+					continue;
+				}
+				checked.push(entity);
+
+				let (position,solid) = match (self.positions.get(entity),self.solids.get(entity)){
+					(Some(&components::Position(position)),Some(solid)) if filter.accepts(entity,solid) => (position,solid),
+					_ => continue,
+				};
+				let intersection = solid.shape.as_ray_cast().and_then(|shape|
+					shape.toi_and_normal_with_ray(&Isometry2::new(position,solid.angle),&ray,true)
+				);
+				if let Some(intersection) = intersection{
+					if intersection.toi <= max_toi && first.map_or(true,|hit| intersection.toi < hit.toi){
+						first = Some(Hit{
+							entity: entity,
+							toi   : intersection.toi,
+							point : origin + direction * intersection.toi,
+							normal: intersection.normal,
+						});
+					}
+				}
+			}
+
+			//Solids in the following cells that were not checked yet can only be hit further away
+			let cell_exit = next.0.min(next.1);
+			if cell_exit >= max_toi || first.map_or(false,|hit| hit.toi <= cell_exit){
+				break;
+			}
+			if next.0 < next.1{
+				cell.0+= step.0;
+				next.0+= delta.0;
+			}else{
+				cell.1+= step.1;
+				next.1+= delta.1;
+			}
+		}
+		first
+	}
+
+	//The first solid hit when moving the shape from `position` by `motion`
+	pub fn shape_cast(&self,shape: &ShapeHandle2<f64>,position: Vector2<f64>,angle: f64,motion: Vector2<f64>,filter: &QueryFilter) -> Option<Hit>{
+		let aabb = shape.aabb(&Isometry2::new(position,angle)).merged(&shape.aabb(&Isometry2::new(position + motion,angle)));
+
+		let mut first: Option<(f64,Entity,Vector2<f64>,&components::Solid)> = None;
+		for (entity,other_pos,other) in self.candidates(&aabb,filter){
+			if let Some(toi) = ::ncollide::query::time_of_impact(
+				&Isometry2::new(position,angle),
+				&motion,
+				shape.deref(),
+				&Isometry2::new(other_pos,other.angle),
+				&zero(),
+				other.shape.deref()
+			){
+				if toi <= 1.0 && first.map_or(true,|(first_toi,..)| toi < first_toi){
+					first = Some((toi,entity,other_pos,other));
+				}
+			}
+		}
+
+		first.map(|(toi,entity,other_pos,other)|{
+			let position = position + motion * toi;
+
+			//The shapes are touching at the time of impact, so the contact gives the point and normal
+			let contact = ::ncollide::query::contact(
+				&Isometry2::new(position,angle),
+				shape.deref(),
+				&Isometry2::new(other_pos,other.angle),
+				other.shape.deref(),
+				1.0
+			);
+			Hit{
+				entity: entity,
+				toi   : toi,
+				point : contact.as_ref().map_or(position,|contact| contact.world2.coords),
+				normal: contact.as_ref().map_or(zero(),|contact| -contact.normal),
+			}
+		})
+	}
+
+	//The solids overlapping the shape. Only touching does not count
+	pub fn overlap_shape(&self,shape: &ShapeHandle2<f64>,position: Vector2<f64>,angle: f64,filter: &QueryFilter) -> Vec<Entity>{
+		let isometry = Isometry2::new(position,angle);
+
+		self.candidates(&shape.aabb(&isometry),filter).into_iter()
+			.filter(|&(_,other_pos,other)| ::ncollide::query::contact(
+				&isometry,
+				shape.deref(),
+				&Isometry2::new(other_pos,other.angle),
+				other.shape.deref(),
+				0.0
+			).map_or(false,|contact| contact.depth > 0.0))
+			.map(|(entity,..)| entity)
+			.collect()
+	}
+
+	//Whether the shape would not overlap any solid
+	#[inline(always)]
+	pub fn is_free(&self,shape: &ShapeHandle2<f64>,position: Vector2<f64>,angle: f64,filter: &QueryFilter) -> bool{
+		self.overlap_shape(shape,position,angle,filter).is_empty()
+	}
+
+	//The solids with bounding boxes intersecting the region
+	pub fn region(&self,aabb: &AABB<Point2<f64>>,filter: &QueryFilter) -> Vec<Entity>{
+		self.candidates(aabb,filter).into_iter()
+			.filter(|&(_,other_pos,other)| aabb.intersects(&other.shape.aabb(&Isometry2::new(other_pos,other.angle))))
+			.map(|(entity,..)| entity)
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests{
+	use amethyst::ecs::{Entity,World};
+	use nalgebra::{Isometry2,Vector2};
+	use ncollide::shape::{Cuboid,ShapeHandle2};
+	use std::f64::INFINITY;
+
+	use *;
+	use super::{QueryFilter,WorldQuery};
+
+	fn world() -> World{
+		let mut world = World::new();
+		world.register::<components::Position>();
+		world.register::<components::Solid>();
+		world
+	}
+
+	fn add_block(world: &mut World,broadphase: &mut data::Broadphase,position: Vector2<f64>,half_extents: Vector2<f64>,ignore: Option<Entity>) -> Entity{
+		let solid = components::Solid{
+			ignore: ignore,
+			..components::Solid::new(
+				data::SolidType::Solid,
+				false,
+				false,
+				data::Material::NORMAL,
+				ShapeHandle2::new(Cuboid::new(half_extents)),
+			)
+		};
+		let aabb = solid.shape.aabb(&Isometry2::new(position,0.0));
+		let entity = world.create_entity()
+			.with(components::Position(position))
+			.with(solid)
+			.build();
+		broadphase.update(entity,&aabb,true);
+		entity
+	}
+
+	#[test]
+	fn raycast_hit(){
+		let mut world = world();
+		let mut broadphase = data::Broadphase::new(64.0);
+		add_block(&mut world,&mut broadphase,Vector2::new(1000.0,0.0),Vector2::new(16.0,16.0),None);
+		let block = add_block(&mut world,&mut broadphase,Vector2::new(200.0,0.0),Vector2::new(16.0,16.0),None);
+
+		let (positions,solids) = (world.read::<components::Position>(),world.read::<components::Solid>());
+		let query = WorldQuery::new(&positions,&solids,&broadphase);
+		let hit = query.raycast(Vector2::new(0.0,0.0),Vector2::new(1.0,0.0),INFINITY,&QueryFilter::new(data::layer::ALL));
+
+		let hit = hit.expect("The ray hit nothing");
+		assert!(hit.entity==block,"Hit the block further away");
+		assert!((hit.toi - 184.0).abs() < 1.0e-6,"Hit at {} instead of 184",hit.toi);
+		assert!((hit.normal - Vector2::new(-1.0,0.0)).norm() < 1.0e-6,"Hit normal is ({},{})",hit.normal[0],hit.normal[1]);
+	}
+
+	#[test]
+	fn raycast_miss(){
+		let mut world = world();
+		let mut broadphase = data::Broadphase::new(64.0);
+		add_block(&mut world,&mut broadphase,Vector2::new(200.0,0.0),Vector2::new(16.0,16.0),None);
+
+		let (positions,solids) = (world.read::<components::Position>(),world.read::<components::Solid>());
+		let query  = WorldQuery::new(&positions,&solids,&broadphase);
+		let filter = QueryFilter::new(data::layer::ALL);
+		assert!(query.raycast(Vector2::new(0.0,0.0),Vector2::new(1.0,0.0),100.0,&filter).is_none(),"Hit beyond the maximum distance");
+		assert!(query.raycast(Vector2::new(0.0,0.0),Vector2::new(-1.0,1.0),INFINITY,&filter).is_none(),"Hit behind the ray");
+		assert!(query.raycast(Vector2::new(0.0,0.0),Vector2::new(1.0,0.0),INFINITY,&QueryFilter::new(data::layer::PLAYER)).is_none(),"Hit a solid in another layer");
+	}
+
+	//Solids ignoring the entity asking are passed through
+	#[test]
+	fn raycast_ignored(){
+		let mut world = world();
+		let mut broadphase = data::Broadphase::new(64.0);
+		let asker = world.create_entity().build();
+		add_block(&mut world,&mut broadphase,Vector2::new(200.0,0.0),Vector2::new(16.0,16.0),Some(asker));
+		let block = add_block(&mut world,&mut broadphase,Vector2::new(400.0,0.0),Vector2::new(16.0,16.0),None);
+
+		let (positions,solids) = (world.read::<components::Position>(),world.read::<components::Solid>());
+		let query = WorldQuery::new(&positions,&solids,&broadphase);
+		let hit = query.raycast(Vector2::new(0.0,0.0),Vector2::new(1.0,0.0),INFINITY,&QueryFilter{ignore: Some(asker),..QueryFilter::new(data::layer::ALL)});
+		assert!(hit.map_or(false,|hit| hit.entity==block),"Did not pass through the ignoring solid");
+	}
+
+	//The bounds limiting the rays grow and shrink with the solids in the broadphase
+	#[test]
+	fn raycast_moved_bounds(){
+		let mut world = world();
+		let mut broadphase = data::Broadphase::new(64.0);
+		let block = add_block(&mut world,&mut broadphase,Vector2::new(200.0,0.0),Vector2::new(16.0,16.0),None);
+		let far   = add_block(&mut world,&mut broadphase,Vector2::new(2000.0,0.0),Vector2::new(16.0,16.0),None);
+		broadphase.remove(far);
+		assert!(broadphase.bounds().map_or(false,|bounds| bounds.maxs()[0] < 1000.0),"The bounds did not shrink after a removal");
+
+		let moved = Vector2::new(1000.0,0.0);
+		world.write::<components::Position>().insert(block,components::Position(moved));
+		broadphase.update(block,&ShapeHandle2::new(Cuboid::new(Vector2::new(16.0,16.0))).aabb(&Isometry2::new(moved,0.0)),false);
+
+		let (positions,solids) = (world.read::<components::Position>(),world.read::<components::Solid>());
+		let query = WorldQuery::new(&positions,&solids,&broadphase);
+		let hit = query.raycast(Vector2::new(0.0,0.0),Vector2::new(1.0,0.0),INFINITY,&QueryFilter::new(data::layer::ALL));
+		assert!(hit.map_or(false,|hit| hit.entity==block && (hit.toi - 984.0).abs() < 1.0e-6),"Did not hit the moved block");
+		assert!(broadphase.bounds().map_or(false,|bounds| bounds.mins()[0] > 200.0),"The bounds did not shrink after moving");
+	}
+}
@@ -1,16 +1,33 @@
 use amethyst::{State,Trans,Engine};
-use amethyst::assets::BoxedErr;
+use amethyst::assets::{AssetFuture,BoxedErr};
 use amethyst::ecs::rendering::{MeshComponent,MaterialComponent,Factory};
 use amethyst::ecs::transform::{Transform,LocalTransform,Child,Init};
 use amethyst::event::{Event,WindowEvent,VirtualKeyCode,KeyboardInput};
 use amethyst::input::InputHandler;
 use amethyst::renderer::{Mesh,Texture,Projection,Camera,MaterialBuilder};
 use amethyst::timing::Time;
+use ncollide::shape::ShapeHandle2;
 
 use *;
 
 pub struct Ingame;
 impl Ingame{
+	//Generates a mesh matching the shape
+	pub fn shape_mesh(engine: &mut Engine,shape: &ShapeHandle2<f64>) -> AssetFuture<MeshComponent>{
+		use std::ops::Deref;
+
+		let verts = util::gen_shape_glvertices(shape.deref());
+		util::load_proc_asset(engine,move |engine|{
+			let mesh = Mesh::build(verts);
+
+			let factory = engine.world.read_resource::<Factory>();
+			factory
+				.create_mesh(mesh)
+				.map(MeshComponent::new)
+				.map_err(BoxedErr::new)
+		})
+	}
+
 	pub fn update_camera(engine: &mut Engine,camera: data::Camera){
 		engine.world.add_resource(Camera{
 			eye    : [0.0, 0.0, 1.0].into(),
@@ -30,20 +47,9 @@ impl State for Ingame{
 	fn on_start(&mut self,engine: &mut Engine){
 		use amethyst::assets::formats::textures::*;
 		use futures::Future;
-		use nalgebra::{Vector2,zero};
-		use ncollide::shape::{Cuboid,ShapeHandle2};
-
-		//Generate a square mesh
-		let square_mesh = util::load_proc_asset(engine,move |engine|{
-			let square_verts = util::gen_rectangle_glvertices(1.0,1.0);
-			let mesh = Mesh::build(square_verts);
+		use nalgebra::{Point2,Vector2,zero};
+		use ncollide::shape::{ConvexHull,Cuboid};
 
-			let factory = engine.world.read_resource::<Factory>();
-			factory
-				.create_mesh(mesh)
-				.map(MeshComponent::new)
-				.map_err(BoxedErr::new)
-		});
 		let square_mtl = util::load_proc_asset(engine,move |engine|{
 			let tex = Texture::from_color_val([1.0 , 1.0 , 1.0 , 1.0]);
 
@@ -73,6 +79,15 @@ impl State for Ingame{
 		engine.world.add_resource(data::Camera::new());
 		engine.world.add_resource(InputHandler::new());
 		engine.world.add_resource(Time::default());
+		engine.world.add_resource(data::Tuning::load(data::Tuning::PATH).unwrap_or_else(|err|{
+			eprintln!("Could not load {}, using the default tuning: {}",data::Tuning::PATH,err);
+			data::Tuning::default()
+		}));
+		engine.world.add_resource(data::PhysicsTime::new(systems::ingame::Physics::STEP));
+		engine.world.add_resource(data::Broadphase::new(64.0));
+		engine.world.add_resource(data::Events::<data::CollisionEvent>::new());
+		engine.world.add_resource(data::Events::<data::TriggerEvent>::new());
+		engine.world.add_resource(data::Events::<data::CrushEvent>::new());
 
 		engine.world.register::<Child>();
 		engine.world.register::<Init>();
@@ -80,34 +95,38 @@ impl State for Ingame{
 
 		//Create a floor
 		{
+			let shape = ShapeHandle2::new(Cuboid::new(Vector2::new(300.0,16.0)));
+			let mesh  = Self::shape_mesh(engine,&shape);
 			engine.world.create_entity()
-				.with(square_mesh.clone())
+				.with(mesh)
 				.with(square_mtl.clone())
 				.with(components::Position(zero()))
 				.with(components::Solid::new(
 					data::SolidType::Solid,
 					false,
 					false,
-					240.0,
-					ShapeHandle2::new(Cuboid::new(Vector2::new(300.0,16.0))),
+					data::Material::NORMAL,
+					shape,
 				))
 				.with(LocalTransform::default())
 				.with(Transform::default())
 				.build();
 		}
 
-		//Create a floor
+		//Create a muddy floor
 		{
+			let shape = ShapeHandle2::new(Cuboid::new(Vector2::new(150.0,16.0)));
+			let mesh  = Self::shape_mesh(engine,&shape);
 			engine.world.create_entity()
-				.with(square_mesh.clone())
+				.with(mesh)
 				.with(square_mtl.clone())
 				.with(components::Position(Vector2::new(640.0,480.0)))
 				.with(components::Solid::new(
 					data::SolidType::Solid,
 					false,
 					false,
-					240.0,
-					ShapeHandle2::new(Cuboid::new(Vector2::new(150.0,16.0))),
+					data::Material::MUD,
+					shape,
 				))
 				.with(LocalTransform::default())
 				.with(Transform::default())
@@ -116,16 +135,62 @@ impl State for Ingame{
 
 		//Create a floor
 		{
+			let shape = ShapeHandle2::new(Cuboid::new(Vector2::new(150.0,16.0)));
+			let mesh  = Self::shape_mesh(engine,&shape);
 			engine.world.create_entity()
-				.with(square_mesh.clone())
+				.with(mesh)
 				.with(square_mtl.clone())
 				.with(components::Position(Vector2::new(200.0,400.0)))
 				.with(components::Solid::new(
 					data::SolidType::Solid,
 					false,
 					false,
-					240.0,
-					ShapeHandle2::new(Cuboid::new(Vector2::new(150.0,16.0))),
+					data::Material::NORMAL,
+					shape,
+				))
+				.with(LocalTransform::default())
+				.with(Transform::default())
+				.build();
+		}
+
+		//Create a slope
+		{
+			let shape = ShapeHandle2::new(ConvexHull::new(vec![
+				Point2::new(-60.0,  0.0),
+				Point2::new( 60.0,  0.0),
+				Point2::new( 60.0,-40.0),
+			]));
+			let mesh  = Self::shape_mesh(engine,&shape);
+			engine.world.create_entity()
+				.with(mesh)
+				.with(square_mtl.clone())
+				.with(components::Position(Vector2::new(290.0,384.0)))
+				.with(components::Solid::new(
+					data::SolidType::Solid,
+					false,
+					false,
+					data::Material::NORMAL,
+					shape,
+				))
+				.with(LocalTransform::default())
+				.with(Transform::default())
+				.build();
+		}
+
+		//Create a fall-through platform
+		{
+			let shape = ShapeHandle2::new(Cuboid::new(Vector2::new(60.0,4.0)));
+			let mesh  = Self::shape_mesh(engine,&shape);
+			engine.world.create_entity()
+				.with(mesh)
+				.with(square_mtl.clone())
+				.with(components::Position(Vector2::new(200.0,320.0)))
+				.with(components::Solid::new(
+					data::SolidType::FallThrough,
+					false,
+					false,
+					data::Material::NORMAL,
+					shape,
 				))
 				.with(LocalTransform::default())
 				.with(Transform::default())
@@ -134,20 +199,26 @@ impl State for Ingame{
 
 		//Create a horizontally moving platform
 		{
+			let shape = ShapeHandle2::new(Cuboid::new(Vector2::new(100.0,8.0)));
+			let mesh  = Self::shape_mesh(engine,&shape);
 			engine.world.create_entity()
-				.with(square_mesh.clone())
+				.with(mesh)
 				.with(square_mtl.clone())
 				.with(components::Position(Vector2::new(0.0,250.0)))
-				.with(components::Solid{
-					velocity: Vector2::new(40.0,0.0),
-					..components::Solid::new(
-						data::SolidType::Solid,
-						false,
-						false,
-						500.0,
-						ShapeHandle2::new(Cuboid::new(Vector2::new(100.0,8.0))),
-					)
-				})
+				.with(components::Solid::new(
+					data::SolidType::Solid,
+					false,
+					false,
+					data::Material::NORMAL,
+					shape,
+				))
+				.with(components::PathMover::new(
+					vec![
+						data::Waypoint{position: Vector2::new(  0.0,250.0),timing: data::PathTiming::Speed(40.0),wait: 1.0},
+						data::Waypoint{position: Vector2::new(240.0,250.0),timing: data::PathTiming::Speed(40.0),wait: 1.0},
+					],
+					data::PathMode::PingPong
+				))
 				.with(LocalTransform::default())
 				.with(Transform::default())
 				.build();
@@ -155,18 +226,47 @@ impl State for Ingame{
 
 		//Create a vertically moving platform
 		{
+			let shape = ShapeHandle2::new(Cuboid::new(Vector2::new(20.0,8.0)));
+			let mesh  = Self::shape_mesh(engine,&shape);
 			engine.world.create_entity()
-				.with(square_mesh.clone())
+				.with(mesh)
 				.with(square_mtl.clone())
 				.with(components::Position(Vector2::new(500.0,480.0)))
+				.with(components::Solid::new(
+					data::SolidType::Solid,
+					false,
+					false,
+					data::Material::NORMAL,
+					shape,
+				))
+				.with(components::PathMover::new(
+					vec![
+						data::Waypoint{position: Vector2::new(500.0,480.0),timing: data::PathTiming::Duration(4.0),wait: 0.5},
+						data::Waypoint{position: Vector2::new(500.0,320.0),timing: data::PathTiming::Duration(4.0),wait: 0.5},
+					],
+					data::PathMode::PingPong
+				))
+				.with(LocalTransform::default())
+				.with(Transform::default())
+				.build();
+		}
+
+		//Create a rotating platform
+		{
+			let shape = ShapeHandle2::new(Cuboid::new(Vector2::new(50.0,6.0)));
+			let mesh  = Self::shape_mesh(engine,&shape);
+			engine.world.create_entity()
+				.with(mesh)
+				.with(square_mtl.clone())
+				.with(components::Position(Vector2::new(120.0,160.0)))
 				.with(components::Solid{
-					velocity: Vector2::new(0.0,-40.0),
+					angular_velocity: 0.5,
 					..components::Solid::new(
 						data::SolidType::Solid,
 						false,
 						false,
-						500.0,
-						ShapeHandle2::new(Cuboid::new(Vector2::new(20.0,8.0))),
+						data::Material::NORMAL,
+						shape,
 					)
 				})
 				.with(LocalTransform::default())
@@ -176,37 +276,140 @@ impl State for Ingame{
 
 		//Create a slippery floor
 		{
+			let shape = ShapeHandle2::new(Cuboid::new(Vector2::new(100.0,16.0)));
+			let mesh  = Self::shape_mesh(engine,&shape);
 			engine.world.create_entity()
-				.with(square_mesh.clone())
+				.with(mesh)
 				.with(square_mtl.clone())
 				.with(components::Position(Vector2::new(420.0,360.0)))
 				.with(components::Solid::new(
 					data::SolidType::Solid,
 					false,
 					false,
-					30.0,
-					ShapeHandle2::new(Cuboid::new(Vector2::new(100.0,16.0))),
+					data::Material::ICE,
+					shape,
 				))
 				.with(LocalTransform::default())
 				.with(Transform::default())
 				.build();
 		}
 
+		//Create a conveyor belt
+		{
+			let shape = ShapeHandle2::new(Cuboid::new(Vector2::new(60.0,8.0)));
+			let mesh  = Self::shape_mesh(engine,&shape);
+			engine.world.create_entity()
+				.with(mesh)
+				.with(square_mtl.clone())
+				.with(components::Position(Vector2::new(560.0,250.0)))
+				.with(components::Solid{
+					surface_velocity: 60.0,
+					..components::Solid::new(
+						data::SolidType::Solid,
+						false,
+						false,
+						data::Material::NORMAL,
+						shape,
+					)
+				})
+				.with(LocalTransform::default())
+				.with(Transform::default())
+				.build();
+		}
+
+		//Create a tilemap. The tiles are drawn by the merged collision shapes, and the physics uses the colliders created by the TilemapColliders system
+		//The meshes are only built here, so the tilemap is static for rendering: editing or moving it afterwards only rebuilds the colliders
+		{
+			let origin  = Vector2::new(-480.0,64.0);
+			let tilemap = components::Tilemap::from_rows(16.0,&[
+				"              ^^      ",
+				"      /######--####\\  ",
+				"######################",
+			]);
+			for part in tilemap.parts(){
+				let mesh = Self::shape_mesh(engine,&part.shape);
+				engine.world.create_entity()
+					.with(mesh)
+					.with(square_mtl.clone())
+					.with(components::Position(origin + part.offset))
+					.with(LocalTransform::default())
+					.with(Transform::default())
+					.build();
+			}
+			engine.world.create_entity()
+				.with(components::Position(origin))
+				.with(tilemap)
+				.build();
+		}
+
+		//Create a ladder
+		{
+			let shape = ShapeHandle2::new(Cuboid::new(Vector2::new(10.0,44.0)));
+			let mesh  = Self::shape_mesh(engine,&shape);
+			engine.world.create_entity()
+				.with(mesh)
+				.with(square_mtl.clone())
+				.with(components::Position(Vector2::new(250.0,340.0)))
+				.with(components::Trigger::new(shape,data::layer::PLAYER))
+				.with(components::Climbable)
+				.with(LocalTransform::default())
+				.with(Transform::default())
+				.build();
+		}
+
+		//Create a pool of water
+		{
+			engine.world.create_entity()
+				.with(components::Position(Vector2::new(-200.0,-60.0)))
+				.with(components::Trigger::new(
+					ShapeHandle2::new(Cuboid::new(Vector2::new(80.0,44.0))),
+					data::layer::ALL,
+				))
+				.with(components::Fluid{
+					buoyancy: 1.2,
+					drag    : 2.0,
+				})
+				.build();
+		}
+
+		//Create a low gravity zone
+		{
+			engine.world.create_entity()
+				.with(components::Position(Vector2::new(60.0,320.0)))
+				.with(components::Trigger::new(
+					ShapeHandle2::new(Cuboid::new(Vector2::new(60.0,120.0))),
+					data::layer::ALL,
+				))
+				.with(components::GravityZone{
+					gravity : Vector2::new(0.0,200.0),
+					priority: 0,
+				})
+				.build();
+		}
+
 		//Create player
 		{
+			let shape = ShapeHandle2::new(Cuboid::new(Vector2::new(16.0,32.0)));
+			let mesh  = Self::shape_mesh(engine,&shape);
 			engine.world.create_entity()
-				.with(square_mesh.clone())
+				.with(mesh)
 				.with(square_mtl.clone())
-				.with(components::Player{id: 0})
+				.with(components::Player::new(0,Vector2::new(500.0,100.0)))
 				.with(components::Position(Vector2::new(500.0,100.0)))
 				.with(components::CollisionCache::new())
-				.with(components::Solid::new(
-					data::SolidType::Solid,
-					true,
-					true,
-					50.0,
-					ShapeHandle2::new(Cuboid::new(Vector2::new(16.0,32.0))),
-				))
+				.with(components::GroundContact::new())
+				.with(components::WallContact::new())
+				.with(components::Mover::new())
+				.with(components::Solid{
+					layer: data::layer::PLAYER,
+					..components::Solid::new(
+						data::SolidType::Solid,
+						true,
+						true,
+						data::Material::NORMAL,
+						shape,
+					)
+				})
 				.with(LocalTransform::default())
 				.with(Transform::default())
 				.build();
@@ -214,19 +417,27 @@ impl State for Ingame{
 
 		//Create player
 		{
+			let shape = ShapeHandle2::new(Cuboid::new(Vector2::new(16.0,32.0)));
+			let mesh  = Self::shape_mesh(engine,&shape);
 			engine.world.create_entity()
-				.with(square_mesh.clone())
+				.with(mesh)
 				.with(square_mtl.clone())
-				.with(components::Player{id: 1})
+				.with(components::Player::new(1,Vector2::new(600.0,100.0)))
 				.with(components::Position(Vector2::new(600.0,100.0)))
 				.with(components::CollisionCache::new())
-				.with(components::Solid::new(
-					data::SolidType::Solid,
-					true,
-					true,
-					50.0,
-					ShapeHandle2::new(Cuboid::new(Vector2::new(16.0,32.0))),
-				))
+				.with(components::GroundContact::new())
+				.with(components::WallContact::new())
+				.with(components::Mover::new())
+				.with(components::Solid{
+					layer: data::layer::PLAYER,
+					..components::Solid::new(
+						data::SolidType::Solid,
+						true,
+						true,
+						data::Material::NORMAL,
+						shape,
+					)
+				})
 				.with(LocalTransform::default())
 				.with(Transform::default())
 				.build();
@@ -4,68 +4,386 @@ pub mod ingame{
 	use amethyst::ecs::input::InputHandler;
 	use amethyst::ecs::{self,Join,System};
 	use amethyst::timing::Time;
+	use nalgebra::Vector2;
+	use std::cmp::Ordering;
+	use std::collections::{HashMap,HashSet};
+	use std::fs;
 	use std::ops::Deref;
+	use std::path::PathBuf;
+	use std::time::SystemTime;
 
 	use *;
 
-	pub struct PlayerInput;
-	impl<'a> System<'a> for PlayerInput{
+	//Maps the input devices to the controls of the players
+	pub struct PlayerControls;
+	impl<'a> System<'a> for PlayerControls{
 		type SystemData = (
-			ecs::WriteStorage<'a,components::Solid>,
 			ecs::WriteStorage<'a,components::Player>,
-			ecs::ReadStorage<'a,components::CollisionCache>,
 			ecs::Fetch<'a,InputHandler>
 		);
 
-		fn run(&mut self,(mut collisions,mut players,collision_caches,input): Self::SystemData){
+		fn run(&mut self,(mut players,input): Self::SystemData){
 			use amethyst::event::VirtualKeyCode;
 			use amethyst::input::ButtonState::*;
 			use amethyst::input::ChangeState::*;
 
+			for &mut components::Player{id,ref mut controls,..} in (&mut players).join(){
+				let (left,right,up,down,jump) = match id{
+					0 => (VirtualKeyCode::Left,VirtualKeyCode::Right,VirtualKeyCode::Up,VirtualKeyCode::Down,VirtualKeyCode::RShift),
+					1 => (VirtualKeyCode::A,VirtualKeyCode::D,VirtualKeyCode::W,VirtualKeyCode::S,VirtualKeyCode::Space),
+					_ => continue,
+				};
+				*controls = data::Controls{
+					left : input.key_is(left ,Pressed(Currently)),
+					right: input.key_is(right,Pressed(Currently)),
+					up   : input.key_is(up   ,Pressed(Currently)),
+					down : input.key_is(down ,Pressed(Currently)),
+					jump : input.key_is(jump ,Pressed(Currently)),
+				};
+			}
+		}
+	}
+
+	pub struct PlayerInput;
+	impl<'a> System<'a> for PlayerInput{
+		type SystemData = (
+			ecs::Entities<'a>,
+			ecs::WriteStorage<'a,components::Solid>,
+			ecs::WriteStorage<'a,components::Player>,
+			ecs::ReadStorage<'a,components::GroundContact>,
+			ecs::WriteStorage<'a,components::WallContact>,
+			ecs::ReadStorage<'a,components::Trigger>,
+			ecs::ReadStorage<'a,components::Climbable>,
+			ecs::WriteStorage<'a,components::Mover>,
+			ecs::Fetch<'a,data::Tuning>,
+			ecs::Fetch<'a,data::PhysicsTime>
+		);
+
+		fn run(&mut self,(entities,mut collisions,mut players,grounds,mut walls,triggers,climbables,mut movers,tuning,physics_time): Self::SystemData){
+			use nalgebra::{dot,zero};
+
+			let time = physics_time.time;
+
+			//The solids inside climbable zones
+			let mut in_climbable: Vec<ecs::Entity> = Vec::new();
+			for(
+				&components::Trigger{ref inside,..},
+				_,
+			) in (
+				&triggers,
+				&climbables,
+			).join(){
+				in_climbable.extend(inside.iter().cloned());
+			}
+
 			for(
-				ref mut player,
-				&mut components::Solid{ref mut velocity,ref mut acceleration,..},
-				&components::CollisionCache{ref position_resolve,..},
+				entity,
+				player,
+				solid,
+				ground,
+				wall,
 			) in (
+				&*entities,
 				&mut players,
 				&mut collisions,
-				&collision_caches,
+				&grounds,
+				&mut walls,
 			).join(){
+				//Movement is relative to the direction of the gravity
+				let up    = solid.up();
+				let right = util::vector_perpendicular(up);
+				let controls  = player.controls;
+				let can_climb = in_climbable.contains(&entity);
+
+				//Pressing up also jumps, except where it would climb
+				let jump = (controls.jump && !player.old_controls.jump) || (controls.up && !player.old_controls.up && !can_climb);
+
+				//Climb when pressing up or down in a climbable zone, unless moving upwards faster than climbing (for example when jumping). Stop when leaving the zone or jumping.
+				//Gravity is suspended while climbing
+				let climbing = can_climb && !jump && (player.climbing || ((controls.up || controls.down) && dot(&solid.velocity,&up) <= tuning.climb_speed));
+				let detached = player.climbing && !climbing;
+				if climbing != player.climbing{
+					player.climbing = climbing;
+					solid.gravity   = !climbing;
+				}
+
+				let components::Solid{ref mut velocity,ref mut acceleration,ref mut fall_through,submerged,..} = *solid;
+				let mut mover = movers.get_mut(entity);
+
+				//Drop down through fall-through platforms
+				*fall_through = controls.down;
+
+				//The physics keeps the acceleration through frames without a step, so it is set again instead of added to
+				*acceleration = zero();
+
+				if let Some(ref mut mover) = mover{
+					if climbing{
+						mover.set_state(data::MoverState::Climbing,time);
+					}else if detached || mover.state==data::MoverState::Climbing{
+						//The physics decides what happens next
+						mover.set_state(data::MoverState::Falling,time);
+					}
+				}
+
+				if climbing{
+					//Move at the climbing speed in the pressed directions, without accelerating
+					let vertical   = (controls.up    as i8 - controls.down as i8) as f64;
+					let horizontal = (controls.right as i8 - controls.left as i8) as f64;
+					*velocity = up.multiply_by(vertical * tuning.climb_speed) + right.multiply_by(horizontal * tuning.climb_speed);
+
+					wall.sliding = false;
+					player.old_controls = controls;
+					continue;
+				}
+
+				//Which side the wall is on (-1.0 for left, 1.0 for right), when touching one in the air
+				let wall_side = if wall.is_touching() && !ground.is_grounded(){
+					-dot(&wall.normal,&right).signum()
+				}else{
+					0.0
+				};
+
+				let mut jumped = false;
+				if jump && !*fall_through{
+					if submerged >= tuning.swim_submerged{
+						//Swimming strokes can be repeated
+						*velocity+= up.multiply_by((tuning.swim_stroke - dot(&*velocity,&up)).max(0.0));
+					}else if ground.is_grounded() || detached{
+						*velocity+= up.multiply_by(tuning.jump_velocity - dot(&*velocity,&up));
+						jumped = true;
+					}else if wall_side != 0.0{
+						//Jump away from the wall
+						*velocity = right.multiply_by(-wall_side * tuning.wall_jump_side) + up.multiply_by(tuning.wall_jump_up);
+						jumped = true;
+					}
+				}
+
+				//Duck while holding down on the ground
+				if let Some(ref mut mover) = mover{
+					let state = mover.state;
+					match state{
+						_ if jumped => mover.set_state(data::MoverState::Jumping,time),
+						data::MoverState::OnGround{normal} if controls.down => mover.set_state(data::MoverState::Ducking{normal: normal},time),
+						data::MoverState::Ducking{normal} if !controls.down => mover.set_state(data::MoverState::OnGround{normal: normal},time),
+						_ => {}
+					}
+				}
+
+				//Slide down slowly when holding towards the wall. The fall speed is limited by the physics in every step
+				wall.sliding = (wall_side < 0.0 && controls.left) || (wall_side > 0.0 && controls.right);
+
 				match player.id{
 					0 =>{
-						if input.key_is(VirtualKeyCode::Up,Pressed(ThisFrame)){
-							//If on ground
-							if position_resolve[1] < 0.0{
-								velocity[1] = -420.0;
-							}
-						}
-						if input.key_is(VirtualKeyCode::Left,Pressed(Currently)){
-							if velocity[0] > -100.0{
-								acceleration[0]-= 1200.0;
+						if controls.left{
+							if dot(&*velocity,&right) > -tuning.walk_speed{
+								*acceleration-= right.multiply_by(tuning.walk_acceleration);
 							}
 						}
-						if input.key_is(VirtualKeyCode::Right,Pressed(Currently)){
-							if velocity[0] < 100.0{
-								acceleration[0]+= 1200.0;
+						if controls.right{
+							if dot(&*velocity,&right) < tuning.walk_speed{
+								*acceleration+= right.multiply_by(tuning.walk_acceleration);
 							}
 						}
 					}
 					1 =>{
-						if input.key_is(VirtualKeyCode::W,Pressed(ThisFrame)){
-							//If on ground
-							if position_resolve[1] < 0.0{
-								velocity[1] = -420.0;
-							}
-						}
-						if input.key_is(VirtualKeyCode::A,Pressed(Currently)){
-							velocity[0] = velocity[0].min(-100.0);
+						if controls.left{
+							let side_velocity = dot(&*velocity,&right);
+							*velocity+= right.multiply_by(side_velocity.min(-tuning.walk_speed) - side_velocity);
 						}
-						if input.key_is(VirtualKeyCode::D,Pressed(Currently)){
-							velocity[0] = velocity[0].max(100.0);
+						if controls.right{
+							let side_velocity = dot(&*velocity,&right);
+							*velocity+= right.multiply_by(side_velocity.max(tuning.walk_speed) - side_velocity);
 						}
 					}
 					_ => {}
 				};
+
+				player.old_controls = controls;
+			}
+		}
+	}
+
+	//Respawns the players crushed in the physics or touching hazards
+	pub struct PlayerRespawn;
+	impl<'a> System<'a> for PlayerRespawn{
+		type SystemData = (
+			ecs::ReadStorage<'a,components::Player>,
+			ecs::WriteStorage<'a,components::Position>,
+			ecs::WriteStorage<'a,components::Solid>,
+			ecs::WriteStorage<'a,components::CollisionCache>,
+			ecs::WriteStorage<'a,components::GroundContact>,
+			ecs::WriteStorage<'a,components::Mover>,
+			ecs::ReadStorage<'a,components::Trigger>,
+			ecs::ReadStorage<'a,components::Hazard>,
+			ecs::Fetch<'a,data::PhysicsTime>,
+			ecs::Fetch<'a,data::Events<data::CrushEvent>>
+		);
+
+		fn run(&mut self,(players,mut positions,mut solids,mut collision_caches,mut grounds,mut movers,triggers,hazards,physics_time,crush_events): Self::SystemData){
+			use nalgebra::zero;
+
+			let mut respawned: Vec<ecs::Entity> = crush_events.iter().map(|event| event.entity).collect();
+			for(
+				&components::Trigger{ref inside,..},
+				_,
+			) in (
+				&triggers,
+				&hazards,
+			).join(){
+				respawned.extend(inside.iter().cloned());
+			}
+
+			for entity in respawned{
+				let spawn = match players.get(entity){
+					Some(player) => player.spawn,
+					None => continue,
+				};
+				if let Some(&mut components::Position(ref mut position)) = positions.get_mut(entity){
+					*position = spawn;
+				}
+				//Nothing from before is carried over to the spawn point
+				if let Some(solid) = solids.get_mut(entity){
+					solid.velocity         = zero();
+					solid.acceleration     = zero();
+					solid.old_position     = spawn;
+					solid.old_velocity     = zero();
+					solid.old_acceleration = zero();
+				}
+				if let Some(cache) = collision_caches.get_mut(entity){
+					*cache = components::CollisionCache::new();
+				}
+				if let Some(ground) = grounds.get_mut(entity){
+					*ground = components::GroundContact::new();
+				}
+				if let Some(mover) = movers.get_mut(entity){
+					mover.reset(physics_time.time);
+				}
+			}
+		}
+	}
+
+	//Creates the solids and hazard triggers of the tilemaps when they have changed or moved, and deletes them with the tilemap
+	pub struct TilemapColliders{
+		built: HashMap<ecs::Entity,(Vector2<f64>,Vec<ecs::Entity>)>, //The position the colliders of every tilemap were created at, and the colliders
+	}
+	impl TilemapColliders{
+		pub fn new() -> Self{TilemapColliders{
+			built: HashMap::new(),
+		}}
+	}
+	impl<'a> System<'a> for TilemapColliders{
+		type SystemData = (
+			ecs::Entities<'a>,
+			ecs::WriteStorage<'a,components::Tilemap>,
+			ecs::WriteStorage<'a,components::Position>,
+			ecs::WriteStorage<'a,components::Solid>,
+			ecs::WriteStorage<'a,components::Trigger>,
+			ecs::WriteStorage<'a,components::Hazard>
+		);
+
+		fn run(&mut self,(entities,mut tilemaps,mut positions,mut solids,mut triggers,mut hazards): Self::SystemData){
+			//Delete the colliders of removed tilemaps
+			self.built.retain(|&tilemap,&mut (_,ref colliders)|{
+				let exists = entities.is_alive(tilemap) && tilemaps.get(tilemap).is_some();
+				if !exists{
+					for &collider in colliders{
+						let _ = entities.delete(collider);
+					}
+				}
+				exists
+			});
+
+			for(
+				entity,
+				tilemap,
+			) in (
+				&*entities,
+				&mut tilemaps,
+			).join(){
+				let origin = match positions.get(entity){
+					Some(&components::Position(position)) => position,
+					None => continue,
+				};
+				let moved = self.built.get(&entity).map_or(true,|&(built_origin,_)| built_origin!=origin);
+				if !tilemap.dirty && !moved{
+					continue;
+				}
+				tilemap.dirty = false;
+
+				let mut colliders = self.built.remove(&entity).map_or_else(Vec::new,|(_,colliders)| colliders);
+				for collider in colliders.drain(..){
+					let _ = entities.delete(collider);
+				}
+				for part in tilemap.parts(){
+					let collider = entities.create();
+					positions.insert(collider,components::Position(origin + part.offset));
+					match part.typ{
+						data::TileType::Hazard =>{
+							triggers.insert(collider,components::Trigger::new(part.shape,data::layer::PLAYER));
+							hazards.insert(collider,components::Hazard);
+						}
+						typ =>{
+							solids.insert(collider,components::Solid::new(
+								if typ==data::TileType::OneWay{data::SolidType::FallThrough}else{data::SolidType::Solid},
+								false,
+								false,
+								tilemap.material,
+								part.shape,
+							));
+						}
+					}
+					colliders.push(collider);
+				}
+				self.built.insert(entity,(origin,colliders));
+			}
+		}
+	}
+
+	//Reloads the tuning when its file changes
+	pub struct TuningReload{
+		path    : PathBuf,
+		modified: Option<SystemTime>,
+		timer   : f64,
+	}
+	impl TuningReload{
+		pub const INTERVAL: f64 = 0.5; //Time (seconds) between checks of the file
+
+		pub fn new<P: Into<PathBuf>>(path: P) -> Self{
+			let path = path.into();
+			TuningReload{
+				modified: Self::modified(&path),
+				path    : path,
+				timer   : 0.0,
+			}
+		}
+
+		fn modified(path: &PathBuf) -> Option<SystemTime>{
+			fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+		}
+	}
+	impl<'a> System<'a> for TuningReload{
+		type SystemData = (
+			ecs::Fetch<'a,Time>,
+			ecs::FetchMut<'a,data::Tuning>
+		);
+
+		fn run(&mut self,(time,mut tuning): Self::SystemData){
+			self.timer+= util::duration_seconds(time.delta_time);
+			if self.timer < Self::INTERVAL{
+				return;
+			}
+			self.timer = 0.0;
+
+			let modified = Self::modified(&self.path);
+			if modified.is_none() || modified==self.modified{
+				return;
+			}
+			self.modified = modified;
+
+			//Keep the old values when the file is invalid so that mistakes while editing do not stop the game. It is loaded again when saved the next time
+			match data::Tuning::load(&self.path){
+				Ok(new)  => *tuning = new,
+				Err(err) => eprintln!("Could not reload {}, keeping the old tuning: {}",self.path.display(),err),
 			}
 		}
 	}
@@ -73,133 +391,457 @@ pub mod ingame{
 	pub struct Render;
 	impl<'a> System<'a> for Render{
 		type SystemData = (
+			ecs::Entities<'a>,
 			ecs::ReadStorage<'a,components::Solid>,
 			ecs::ReadStorage<'a,components::Position>,
-			ecs::WriteStorage<'a,LocalTransform>
+			ecs::WriteStorage<'a,LocalTransform>,
+			ecs::Fetch<'a,data::PhysicsTime>
 		);
 
-		fn run(&mut self,(collisions,positions,mut locals): Self::SystemData){
-			use nalgebra::Isometry2;
+		fn run(&mut self,(entities,solids,positions,mut locals,physics_time): Self::SystemData){
+			//Solids are drawn between the previous and the current physics step by the time left in the accumulator, so that the movement is smooth at any frame rate
+			let alpha = physics_time.alpha();
 
 			for(
+				entity,
 				&components::Position(position),
-				&components::Solid{ref shape,..},
 				ref mut local
 			) in (
+				&*entities,
 				&positions,
-				&collisions,
 				&mut locals
 			).join(){
-				//Update the renderable corresponding to this entity
-				let aabb = shape.aabb(&Isometry2::new(position,0.0));
-				let mins = aabb.center();
-				let len  = aabb.maxs() - aabb.mins();
-				local.translation[0] = mins[0] as f32;
-				local.translation[1] = mins[1] as f32;
-				local.scale = [
-					len[0] as f32,
-					len[1] as f32,
-					1.0
-				];
+				let (position,angle) = solids.get(entity).map_or((position,0.0),|solid|(
+					solid.old_position + (position - solid.old_position) * alpha,
+					solid.old_angle + (solid.angle - solid.old_angle) * alpha,
+				));
+
+				//Update the renderable corresponding to this entity. The mesh is generated from the shape, so no scaling is needed
+				local.translation[0] = position[0] as f32;
+				local.translation[1] = position[1] as f32;
+
+				//Rotation around the z axis as a quaternion [w,x,y,z]
+				local.rotation = [(angle/2.0).cos() as f32,0.0,0.0,(angle/2.0).sin() as f32];
 			}
 		}
 	}
 
-	pub struct Physics;
+	pub struct Physics{
+		placed: HashSet<ecs::Entity>, //The solids seen in earlier frames
+	}
 	impl Physics{
-		pub const AIR_FRICTION: f64 = 20.0; //pixels/seconds^2
-		pub const GRAVITY: f64 = 600.0;
+		pub const STEP: f64 = 1.0/120.0; //Default duration of a physics step in seconds
+		pub const SOLVER_ITERATIONS: u32 = 8; //Maximum number of collision resolvement passes in a step
+		pub const SOLVER_TOLERANCE: f64 = 0.01; //Penetration depth (pixels) where the contacts are considered resolved
+		pub const BLOCKED_THRESHOLD: f64 = 0.1; //Minimum projection of a direction on a blocked contact normal for the direction to count as blocked
+
+		pub const GROUND_NORMAL_MIN: f64 = 0.64; //Minimum projection of a contact normal on the down direction for the contact to count as ground (slopes up to about 50 degrees)
+		pub const WALL_NORMAL_MAX: f64 = 0.3; //Maximum projection of a contact normal on the down direction for the contact to count as a wall
+
+		pub const CCD_VELOCITY: f64 = 960.0; //Speed (pixels/seconds) where continuous collision detection is used
+
+		pub const CRUSH_OPPOSITE: f64 = 0.7; //Minimum negated projection of two contact normals on each other for the contacts to count as squeezing from opposite sides
+		pub const CRUSH_DEPTH: f64 = 4.0; //Penetration depth (pixels) of the squeezing contacts together where a solid counts as crushed
 
 		#[inline(always)]
-		pub fn new() -> Self{Physics}
-	}
-	impl<'a> System<'a> for Physics{
-		type SystemData = (
-			ecs::WriteStorage<'a,components::CollisionCache>,
-			ecs::WriteStorage<'a,components::Position>,
-			ecs::WriteStorage<'a,components::Solid>,
-			ecs::Fetch<'a,Time>
-		);
-		fn run(&mut self,(mut collision_caches,mut positions,mut solids,time) : Self::SystemData){
+		pub fn new() -> Self{Physics{
+			placed: HashSet::new(),
+		}}
+
+		//Whether a fall-through platform should be collided with. They are only solid when landing on them from above
+		fn lands_on_fall_through(this: &components::Solid,this_vel: &Vector2<f64>,other: &components::Solid) -> bool{
+			use nalgebra::{Isometry2,dot};
+			use util;
+
+			let down = this.down();
+			if this.fall_through || dot(&(*this_vel - other.velocity),&down) < 0.0{
+				return false;
+			}
+			let this_old_bottom =  util::aabb_extent(&this.shape.aabb(&Isometry2::new(this.old_position,this.old_angle)),&down);
+			let other_old_top   = -util::aabb_extent(&other.shape.aabb(&Isometry2::new(other.old_position,other.old_angle)),&-down);
+			this_old_bottom <= other_old_top + Self::SOLVER_TOLERANCE
+		}
+
+		//The contact with the ground in the contacts from the last step
+		pub fn ground_contact<'c>(cache: &'c components::CollisionCache,down: &Vector2<f64>) -> Option<&'c data::Contact>{
+			use nalgebra::dot;
+
+			cache.contacts.iter()
+				.filter(|contact| dot(&contact.normal,down) >= Self::GROUND_NORMAL_MIN)
+				.max_by(|a,b| dot(&a.normal,down).partial_cmp(&dot(&b.normal,down)).unwrap_or(Ordering::Equal))
+		}
+
+		//The contact with a wall on either side in the contacts from the last step. Only solids without collision checking are walls, so that other players cannot be slid down or jumped off
+		pub fn wall_contact<'c>(cache: &'c components::CollisionCache,solids: &ecs::WriteStorage<components::Solid>,down: &Vector2<f64>) -> Option<&'c data::Contact>{
+			use nalgebra::dot;
+
+			cache.contacts.iter()
+				.filter(|contact| dot(&contact.normal,down).abs() <= Self::WALL_NORMAL_MAX)
+				.filter(|contact| solids.get(contact.other).map_or(false,|other| !other.check_movement))
+				.min_by(|a,b| dot(&a.normal,down).abs().partial_cmp(&dot(&b.normal,down).abs()).unwrap_or(Ordering::Equal))
+		}
+
+		//Advances the simulation by exactly `delta_time` seconds
+		fn step(
+			delta_time      : f64,
+			time            : f64,
+			tuning          : &data::Tuning,
+			entities        : &ecs::Entities,
+			broadphase      : &mut data::Broadphase,
+			collision_events: &mut data::Events<data::CollisionEvent>,
+			trigger_events  : &mut data::Events<data::TriggerEvent>,
+			crush_events    : &mut data::Events<data::CrushEvent>,
+			collision_caches: &mut ecs::WriteStorage<components::CollisionCache>,
+			grounds         : &mut ecs::WriteStorage<components::GroundContact>,
+			walls           : &mut ecs::WriteStorage<components::WallContact>,
+			movers          : &mut ecs::WriteStorage<components::Mover>,
+			positions       : &mut ecs::WriteStorage<components::Position>,
+			solids          : &mut ecs::WriteStorage<components::Solid>,
+			triggers        : &mut ecs::WriteStorage<components::Trigger>,
+			path_movers     : &mut ecs::WriteStorage<components::PathMover>,
+			gravity_zones   : &ecs::ReadStorage<components::GravityZone>,
+			fluids          : &ecs::ReadStorage<components::Fluid>,
+		){
 			use nalgebra::{Isometry2,dot,zero};
+			use ncollide::bounding_volume::BoundingVolume;
+			use std::mem;
 			use util;
 
-			let delta_time = time.delta_time.subsec_nanos() as f64 / 1.0e9;
+			//Gravity of the zones for every solid inside one. The zone with the highest priority is used when overlapping
+			let mut zone_gravities: HashMap<ecs::Entity,(i32,Vector2<f64>)> = HashMap::new();
+			for(
+				&components::Trigger{ref inside,..},
+				zone,
+			) in (
+				&*triggers,
+				&*gravity_zones,
+			).join(){
+				for &entity in inside{
+					if zone_gravities.get(&entity).map_or(true,|&(priority,_)| zone.priority > priority){
+						zone_gravities.insert(entity,(zone.priority,zone.gravity));
+					}
+				}
+			}
+
+			//How much of every solid inside a fluid is submerged, and the buoyancy and drag of the fluid. The fluid covering most of the solid is used when overlapping
+			let mut submersions: HashMap<ecs::Entity,(f64,f64,f64)> = HashMap::new();
+			for(
+				this,
+				&components::Position(this_pos),
+				&components::Trigger{ref shape,ref inside,..},
+				fluid,
+			) in (
+				&**entities,
+				&*positions,
+				&*triggers,
+				&*fluids,
+			).join(){
+				let fluid_aabb = shape.aabb(&Isometry2::new(this_pos,solids.get(this).map_or(0.0,|solid| solid.angle)));
+				for &entity in inside{
+					let aabb = match (positions.get(entity),solids.get(entity)){
+						(Some(&components::Position(position)),Some(solid)) => solid.shape.aabb(&Isometry2::new(position,solid.angle)),
+						_ => continue,
+					};
+					let submerged = util::aabb_overlap(&aabb,&fluid_aabb);
+					if submersions.get(&entity).map_or(true,|&(other_submerged,..)| submerged > other_submerged){
+						submersions.insert(entity,(submerged,fluid.buoyancy,fluid.drag));
+					}
+				}
+			}
+
+			//Derive the velocity of the solids following paths
+			for(
+				&components::Position(position),
+				path,
+				&mut components::Solid{ref mut velocity,..},
+			) in (
+				&*positions,
+				&mut *path_movers,
+				&mut *solids,
+			).join(){
+				*velocity = path.step(position,delta_time);
+			}
 
 			//Step movement (using something like Velocity Verlet Integration)
 			for(
+				entity,
 				&mut components::Position(ref mut position),
-				&mut components::Solid{ref mut velocity,ref mut acceleration,ref mut old_position,ref mut old_velocity,ref mut old_acceleration,gravity,..},
+				&mut components::Solid{ref mut velocity,ref acceleration,ref mut old_position,ref mut old_velocity,ref mut old_acceleration,ref mut angle,ref mut old_angle,angular_velocity,gravity,gravity_scale,gravity_direction,ref mut current_gravity,ref mut submerged,..},
 			) in (
-				&mut positions,
-				&mut solids,
+				&**entities,
+				&mut *positions,
+				&mut *solids,
 			).join(){
-				//Update acceleration with gravity
-				if gravity{
-					acceleration[1]+= Self::GRAVITY;
+				//The acceleration from other systems is kept for every step in the frame
+				let mut acceleration = *acceleration;
+
+				//The gravity from the zone the solid is in or the world, with the direction and scale of the solid
+				let zone_gravity = zone_gravities.get(&entity).map_or(Vector2::new(0.0,tuning.gravity),|&(_,gravity)| gravity);
+				*current_gravity = match gravity_direction{
+					Some(direction) => direction.multiply_by(zone_gravity.norm()),
+					None            => zone_gravity,
+				}.multiply_by(gravity_scale);
+				let down = util::gravity_down(*current_gravity);
+
+				//Update acceleration with gravity. The buoyancy of fluids pushes against it
+				let (fluid_submerged,buoyancy,_) = submersions.get(&entity).cloned().unwrap_or((0.0,0.0,0.0));
+				*submerged = fluid_submerged;
+				let mut gravity_acceleration = if gravity{
+					current_gravity.multiply_by(1.0 - buoyancy*fluid_submerged)
+				}else{
+					zero()
+				};
+
+				//When standing on the ground
+				if let Some(normal) = collision_caches.get(entity).and_then(|cache| Self::ground_contact(cache,&down)).map(|contact| -contact.normal){
+					//Gravity only presses against the ground so that nothing slides down slopes
+					gravity_acceleration = dot(&gravity_acceleration,&normal)*normal;
+
+					//Follow the surface when not moving upwards so that walking down slopes does not bounce. The speed along the ground is kept
+					if dot(&*velocity,&down) >= 0.0{
+						let side    = util::vector_perpendicular(down);
+						let tangent = util::vector_perpendicular(normal);
+						*velocity = tangent.multiply_by(dot(&*velocity,&side) / dot(&tangent,&side));
+					}
 				}
+				acceleration+= gravity_acceleration;
 
 				*old_position = *position;
 				*position+= velocity.multiply_by(delta_time) + acceleration.multiply_by(delta_time*delta_time / 2.0)
 				;
 
 				*old_velocity = *velocity;
-				*velocity+= (*acceleration + *old_acceleration).multiply_by(delta_time / 2.0);
+				*velocity+= (acceleration + *old_acceleration).multiply_by(delta_time / 2.0);
 
-				*old_acceleration = *acceleration;
-				*acceleration = zero();
+				//Slide down walls slowly when the controller asks for it
+				if walls.get(entity).map_or(false,|wall| wall.sliding && wall.is_touching()){
+					let fall_velocity = dot(&*velocity,&down);
+					if fall_velocity > tuning.wall_slide_speed{
+						*velocity-= down.multiply_by(fall_velocity - tuning.wall_slide_speed);
+					}
+				}
+
+				*old_acceleration = acceleration;
+
+				*old_angle = *angle;
+				*angle+= angular_velocity*delta_time;
 			}
 
-			//Process collision checking
+			//Update the broadphase with the new positions
+			broadphase.retain(|entity| entities.is_alive(entity) && (solids.get(entity).is_some() || triggers.get(entity).is_some()));
 			for(
-				&components::Position(this_pos),
-				&components::Solid{velocity: ref this_vel,shape: ref this_shape,check_movement,friction: this_friction,..},
-				&mut components::CollisionCache{ref mut position_resolve,ref mut velocity_resolve,ref mut friction_total,..},
+				entity,
+				&components::Position(position),
 			) in (
-				&positions,
-				&solids,
-				&mut collision_caches,
+				&**entities,
+				&*positions,
+			).join(){
+				//Static solids never move, so they only have to be inserted once. Triggers may be moved by other systems
+				let fixed = match (solids.get(entity),triggers.get(entity)){
+					(Some(solid),None) => !solid.check_movement && solid.velocity.norm_squared() == 0.0 && solid.angular_velocity == 0.0,
+					_ => false,
+				};
+				if fixed && broadphase.is_fixed(entity){
+					continue;
+				}
+
+				let isometry = Isometry2::new(position,solids.get(entity).map_or(0.0,|solid| solid.angle));
+				let aabb = match (solids.get(entity),triggers.get(entity)){
+					(Some(solid),None) => solid.shape.aabb(&isometry),
+					(None,Some(trigger)) => trigger.shape.aabb(&isometry),
+					(Some(solid),Some(trigger)) => solid.shape.aabb(&isometry).merged(&trigger.shape.aabb(&isometry)),
+					(None,None) => continue,
+				};
+				broadphase.update(entity,&aabb,fixed);
+			}
+
+			//Reset the resolvement data. The contacts from the last step are kept for the collision events
+			let mut old_contacts = HashMap::new();
+			for(
+				entity,
+				&mut components::CollisionCache{ref mut position_resolve,ref mut velocity_resolve,ref mut contacts},
+			) in (
+				&**entities,
+				&mut *collision_caches,
 			).join(){
-				//Reset the resolvement data
 				*position_resolve = zero();
 				*velocity_resolve = zero();
-				*friction_total   = zero();
-
-				//If this is not a static object (no collision checking)
-				if check_movement{
-					//Check for every other existing object
-					for(
-						&components::Position(other_pos),
-						&components::Solid{friction: other_friction,shape: ref other_shape,velocity: ref other_vel, old_position: ref other_old_pos,check_movement: other_check_movement,..},
-					) in (
-						&positions,
-						&solids,
-					).join(){
+				old_contacts.insert(entity,mem::replace(contacts,Vec::new()));
+			}
+
+			//Only objects with collision checking are moved by the resolvement. The others are static or kinematic
+			let movers: Vec<_> = (&**entities,&*solids,&*collision_caches).join()
+				.filter(|&(_,solid,_)| solid.check_movement)
+				.map(|(entity,_,_)| entity)
+				.collect();
+
+			//Continuous collision detection for fast objects so that they do not pass through thin solids between steps
+			for &this in &movers{
+				let (this_pos,this_old_pos,this_vel,this_shape,this_angle,this_old_angle) = match (positions.get(this),solids.get(this)){
+					(Some(&components::Position(position)),Some(solid)) if solid.fast || solid.velocity.norm_squared() >= Self::CCD_VELOCITY*Self::CCD_VELOCITY =>
+						(position,solid.old_position,solid.velocity,solid.shape.clone(),solid.angle,solid.old_angle),
+					_ => continue,
+				};
+				let this_motion = this_pos - this_old_pos;
+
+				//Find the first object in the way
+				let swept_aabb = this_shape.aabb(&Isometry2::new(this_old_pos,this_old_angle)).merged(&this_shape.aabb(&Isometry2::new(this_pos,this_angle)));
+				let mut first: Option<(f64,ecs::Entity)> = None;
+				for other in broadphase.query(&swept_aabb){
+					if other==this{
+						continue;
+					}
+
+					let (&components::Position(other_pos),other_solid) = match (positions.get(other),solids.get(other)){
+						(Some(position),Some(solid)) => (position,solid),
+						_ => continue,
+					};
+					//Skip objects in layers that this does not collide with
+					if !solids.get(this).map_or(false,|this_solid| this_solid.collides_with(this,other_solid,other)){
+						continue;
+					}
+					if other_solid.typ==data::SolidType::FallThrough && !solids.get(this).map_or(false,|this_solid| Self::lands_on_fall_through(this_solid,&this_vel,other_solid)){
+						continue;
+					}
+
+					//Already touching at the start of the step. This is handled by the usual collision checking
+					if ::ncollide::query::contact(
+						&Isometry2::new(this_old_pos,this_old_angle),
+						this_shape.deref(),
+						&Isometry2::new(other_solid.old_position,other_solid.old_angle),
+						other_solid.shape.deref(),
+						Self::SOLVER_TOLERANCE
+					).is_some(){
+						continue;
+					}
+
+					if let Some(toi) = ::ncollide::query::time_of_impact(
+						&Isometry2::new(this_old_pos,this_old_angle),
+						&this_motion,
+						this_shape.deref(),
+						&Isometry2::new(other_solid.old_position,other_solid.old_angle),
+						&(other_pos - other_solid.old_position),
+						other_solid.shape.deref()
+					){
+						if toi < first.map_or(1.0,|(first_toi,_)| first_toi){
+							first = Some((toi,other));
+						}
+					}
+				}
+
+				//Stop at the first contact
+				if let Some((toi,other)) = first{
+					let position = this_old_pos + this_motion.multiply_by(toi);
+					if let Some(&mut components::Position(ref mut this_position)) = positions.get_mut(this){
+						*this_position = position;
+					}
+
+					//Stop moving into the other solid. It is touching at the start of the next step, where it is not checked here anymore
+					let resolve = match (positions.get(other),solids.get(other)){
+						(Some(&components::Position(other_pos)),Some(other_solid)) => ::ncollide::query::contact(
+							&Isometry2::new(position,this_old_angle),
+							this_shape.deref(),
+							&Isometry2::new(other_solid.old_position + (other_pos - other_solid.old_position).multiply_by(toi),other_solid.old_angle),
+							other_solid.shape.deref(),
+							1.0
+						).map(|contact| -contact.normal.multiply_by(dot(&(this_vel - other_solid.velocity),&contact.normal).max(0.0))),
+						_ => None,
+					};
+					if let (Some(resolve),Some(solid)) = (resolve,solids.get_mut(this)){
+						solid.velocity+= resolve;
+					}
+				}
+			}
+
+			//Normals of the contacts that cannot be resolved by moving in their direction, for every mover. A mover is blocked by static solids, and by movers that are blocked themselves
+			let mut blocked: HashMap<ecs::Entity,Vec<Vector2<f64>>> = HashMap::new();
+			fn is_blocked(blocked: &HashMap<ecs::Entity,Vec<Vector2<f64>>>,entity: ecs::Entity,direction: &Vector2<f64>) -> bool{
+				blocked.get(&entity).map_or(false,|normals| normals.iter().any(|normal| dot(normal,direction) > Physics::BLOCKED_THRESHOLD))
+			}
+
+			//Process collision checking.
+			//Every contact is resolved immediately so that the following checks see the resolved positions, and the checks are repeated until nothing is penetrating anymore. This prevents chains of movers from being pushed into solids.
+			for _ in 0..Self::SOLVER_ITERATIONS{
+				let mut max_depth: f64 = 0.0;
+
+				for &this in &movers{
+					let (mut this_pos,mut this_vel,this_shape,this_angle,down) = match (positions.get(this),solids.get(this)){
+						(Some(&components::Position(position)),Some(solid)) => (position,solid.velocity,solid.shape.clone(),solid.angle,solid.down()),
+						_ => continue,
+					};
+					let mut position_resolve: Vector2<f64> = zero();
+					let mut velocity_resolve: Vector2<f64> = zero();
+					let mut new_contacts     = Vec::new();
+
+					//Check for every other object near this one
+					for other in broadphase.query(&this_shape.aabb(&Isometry2::new(this_pos,this_angle))){
 						//Skip collision with itself
-						if (this_shape as *const _)==(other_shape as *const _){
+						if other==this{
+							continue;
+						}
+
+						let (
+							&components::Position(other_pos),
+							other_solid,
+						) = match (positions.get(other),solids.get(other)){
+							(Some(position),Some(solid)) => (position,solid),
+							_ => continue,
+						};
+						let &components::Solid{shape: ref other_shape,velocity: ref other_vel, old_position: ref other_old_pos,check_movement: other_check_movement,typ: other_typ,angle: other_angle,old_angle: other_old_angle,..} = other_solid;
+
+						//Skip objects in layers that this does not collide with
+						if !solids.get(this).map_or(false,|this_solid| this_solid.collides_with(this,other_solid,other)){
+							continue;
+						}
+						if other_typ==data::SolidType::FallThrough && !solids.get(this).map_or(false,|this_solid| Self::lands_on_fall_through(this_solid,&this_vel,other_solid)){
 							continue;
 						}
 
 						//If it made contact to something
 						if let Some(contact) = ::ncollide::query::contact(
-							&Isometry2::new(this_pos,zero()),
+							&Isometry2::new(this_pos,this_angle),
 							this_shape.deref(),
-							&Isometry2::new(other_pos,zero()),
+							&Isometry2::new(other_pos,other_angle),
 							other_shape.deref(),
 							0.0
 						){
+							//Fall-through platforms only push upwards
+							if other_typ==data::SolidType::FallThrough && dot(&contact.normal,&down) <= 0.0{
+								continue;
+							}
+
 							if contact.depth != 0.0{
-								//Friction (Solid)
-								*friction_total+= this_friction + other_friction;
+								max_depth = max_depth.max(contact.depth);
+
+								//The contact from an earlier pass in this step
+								let previous = new_contacts.iter().find(|c: &&data::Contact| c.other==other).cloned()
+									.or_else(|| collision_caches.get(this).and_then(|cache| cache.contacts.iter().find(|c| c.other==other).cloned()));
+
+								//Whether the objects are able to move away from each other
+								let this_blocked  = is_blocked(&blocked,this,&-contact.normal);
+								let other_blocked = !other_check_movement || is_blocked(&blocked,other,&contact.normal);
+								if other_blocked{
+									blocked.entry(this).or_insert_with(Vec::new).push(contact.normal);
+								}
+
+								//Subtracts the velocity projected on the contact normal when moving towards each other (TODO: Stops when moving towards edge while falling/jumping)
+								let relative_velocity = this_vel - *other_vel;
+								let projected_vel = dot(&relative_velocity,&contact.normal);
+								let impulse = previous.map_or(0.0,|c| c.impulse) + projected_vel.max(0.0);
+								if projected_vel > 0.0{
+									let resolve = -projected_vel*contact.normal;
+									this_vel+= resolve;
+									velocity_resolve+= resolve;
+								}
 
-								//Combine with other possible collision resolvements
-								//Subtracts the velocity projected on the contact normal (TODO: Stops when moving towards edge while falling/jumping)
-								*velocity_resolve+= -dot(&(this_vel - other_vel),&contact.normal)*contact.normal;
 								//Subtracts the position by the contact depth.
-								//Both object tries to resolve the contact, and how much each of them resolves depends on the ratio of how much each contributed to the contact based on the velocity
-								//TODO: When one player walks into a wall, and the other walks into the player walking into a wall, they both get pushed into solids. This is probably because collision checking checks objects with the new position that have not been collision checked yet, and when they resolve, others will not be able to follow.
-								let k = {
-									let this_projected  = dot(this_vel,&contact.normal).abs();
+								//When neither or both are blocked, both object tries to resolve the contact, and how much each of them resolves depends on the ratio of how much each contributed to the contact based on the velocity
+								let k = if other_blocked && !this_blocked{
+									1.0
+								}else if this_blocked && !other_blocked{
+									0.0
+								}else{
+									let this_projected  = dot(&this_vel,&contact.normal).abs();
 									let other_projected = dot(other_vel,&contact.normal).abs();
 									if this_projected==0.0 && other_projected==0.0{
 										1.0
@@ -207,34 +849,454 @@ pub mod ingame{
 										this_projected / (this_projected + other_projected)
 									}
 								};
-								*position_resolve+= -contact.normal.multiply_by((k * contact.depth).abs());
+								let resolve = if dot(&contact.normal,&down) >= Self::GROUND_NORMAL_MIN{
+									//Push straight up from the ground so that nothing slides sideways on slopes
+									-down.multiply_by((k * contact.depth / dot(&contact.normal,&down)).abs())
+								}else{
+									-contact.normal.multiply_by((k * contact.depth).abs())
+								};
+								this_pos+= resolve;
+								position_resolve+= resolve;
 
-								//Touching moving solids that do not have collision checking
-								if !other_check_movement{
-									*position_resolve+= other_pos - other_old_pos;
+								//Things that should only happen once for every touched object in a step
+								if previous.is_none(){
+									//Touching moving solids that do not have collision checking. Follows both the movement and the rotation
+									if !other_check_movement{
+										let offset  = this_pos - other_old_pos;
+										let resolve = other_pos - other_old_pos + util::vector_rotate(offset,other_angle - other_old_angle) - offset;
+										this_pos+= resolve;
+										position_resolve+= resolve;
+									}
 								}
+
+								new_contacts.retain(|c: &data::Contact| c.other!=other);
+								new_contacts.push(data::Contact{
+									other            : other,
+									normal           : contact.normal,
+									depth            : contact.depth,
+									relative_velocity: previous.map_or(relative_velocity,|c| c.relative_velocity),
+									impulse          : impulse,
+								});
 							}
 						}
 					}
+
+					//Apply resolvement from collision checking
+					if let Some(&mut components::Position(ref mut position)) = positions.get_mut(this){
+						*position = this_pos;
+					}
+					if let Some(&mut components::Solid{ref mut velocity,..}) = solids.get_mut(this){
+						*velocity = this_vel;
+					}
+					if let Some(cache) = collision_caches.get_mut(this){
+						cache.position_resolve+= position_resolve;
+						cache.velocity_resolve+= velocity_resolve;
+						for contact in new_contacts{
+							cache.contacts.retain(|c| c.other!=contact.other);
+							cache.contacts.push(contact);
+						}
+					}
+				}
+
+				if max_depth <= Self::SOLVER_TOLERANCE{
+					break;
 				}
 			}
 
-			//Apply resolvement from collision checking
+			//Detect movers squeezed between solids from opposite sides. The solver cannot resolve this, so it is left to the game
+			for &this in &movers{
+				let cache = match collision_caches.get(this){
+					Some(cache) => cache,
+					None => continue,
+				};
+
+				//The contacts that cannot be pushed away
+				let blocking: Vec<&data::Contact> = cache.contacts.iter()
+					.filter(|contact| solids.get(contact.other).map_or(false,|other| !other.check_movement) || is_blocked(&blocked,contact.other,&contact.normal))
+					.collect();
+
+				let mut crush: Option<(f64,Vector2<f64>)> = None;
+				for (i,a) in blocking.iter().enumerate(){
+					for b in &blocking[i+1..]{
+						let depth = a.depth + b.depth;
+						if dot(&a.normal,&b.normal) <= -Self::CRUSH_OPPOSITE && depth >= Self::CRUSH_DEPTH && crush.map_or(true,|(deepest,_)| depth > deepest){
+							crush = Some((depth,a.normal));
+						}
+					}
+				}
+				if let Some((depth,normal)) = crush{
+					crush_events.push(data::CrushEvent{
+						entity: this,
+						normal: normal,
+						depth : depth,
+					});
+				}
+			}
+
+			//Check which solids are inside the triggers
 			for(
-				&mut components::Position(ref mut position),
+				this,
+				&components::Position(this_pos),
+				&mut components::Trigger{ref shape,mask,ref mut inside},
+			) in (
+				&**entities,
+				&*positions,
+				&mut *triggers,
+			).join(){
+				let mut new_inside = Vec::new();
+
+				//Triggers attached to solids follow their rotation
+				let this_isometry = Isometry2::new(this_pos,solids.get(this).map_or(0.0,|solid| solid.angle));
+
+				for other in broadphase.query(&shape.aabb(&this_isometry)){
+					if other==this{
+						continue;
+					}
+
+					let (&components::Position(other_pos),other_solid) = match (positions.get(other),solids.get(other)){
+						(Some(position),Some(solid)) => (position,solid),
+						_ => continue,
+					};
+					if (mask & other_solid.layer)==0{
+						continue;
+					}
+
+					if ::ncollide::query::contact(
+						&this_isometry,
+						shape.deref(),
+						&Isometry2::new(other_pos,other_solid.angle),
+						other_solid.shape.deref(),
+						0.0
+					).is_some(){
+						trigger_events.push(data::TriggerEvent{
+							state  : if inside.contains(&other){data::ContactState::Persisted}else{data::ContactState::Started},
+							trigger: this,
+							entity : other,
+						});
+						new_inside.push(other);
+					}
+				}
+
+				for &other in inside.iter().filter(|&&other| !new_inside.contains(&other)){
+					trigger_events.push(data::TriggerEvent{
+						state  : data::ContactState::Ended,
+						trigger: this,
+						entity : other,
+					});
+				}
+				*inside = new_inside;
+			}
+
+			//Report the changes in the contacts
+			for(
+				this,
+				&components::CollisionCache{ref contacts,..},
+			) in (
+				&**entities,
+				&*collision_caches,
+			).join(){
+				let old_contacts = old_contacts.remove(&this).unwrap_or_else(Vec::new);
+				let this_vel = solids.get(this).map_or(zero(),|solid| solid.velocity);
+
+				for contact in contacts{
+					collision_events.push(data::CollisionEvent{
+						state            : if old_contacts.iter().any(|c| c.other==contact.other){data::ContactState::Persisted}else{data::ContactState::Started},
+						entity           : this,
+						other            : contact.other,
+						normal           : contact.normal,
+						depth            : contact.depth,
+						relative_velocity: contact.relative_velocity,
+					});
+				}
+				for contact in old_contacts.iter().filter(|old| !contacts.iter().any(|c| c.other==old.other)){
+					collision_events.push(data::CollisionEvent{
+						state            : data::ContactState::Ended,
+						entity           : this,
+						other            : contact.other,
+						normal           : contact.normal,
+						depth            : contact.depth,
+						relative_velocity: this_vel - solids.get(contact.other).map_or(zero(),|solid| solid.velocity),
+					});
+				}
+			}
+
+			//Update what everything is standing on
+			for(
+				entity,
+				cache,
+				ground,
+			) in (
+				&**entities,
+				&*collision_caches,
+				&mut *grounds,
+			).join(){
+				let down    = solids.get(entity).map_or(Vector2::new(0.0,1.0),|solid| solid.down());
+				let contact = Self::ground_contact(cache,&down);
+				let entity = contact.map(|contact| contact.other);
+
+				if entity.is_some() != ground.entity.is_some(){
+					ground.time = 0.0;
+				}else{
+					ground.time+= delta_time;
+				}
+				ground.entity = entity;
+				if let Some(contact) = contact{
+					ground.normal   = -contact.normal;
+					ground.velocity = solids.get(contact.other).map_or(zero(),|solid| solid.velocity + util::vector_perpendicular(-contact.normal).multiply_by(solid.surface_velocity));
+				}else{
+					ground.normal   = zero();
+					ground.velocity = zero();
+				}
+			}
+
+			//Update which walls everything is touching
+			for(
+				entity,
+				cache,
+				wall,
+			) in (
+				&**entities,
+				&*collision_caches,
+				&mut *walls,
+			).join(){
+				let down    = solids.get(entity).map_or(Vector2::new(0.0,1.0),|solid| solid.down());
+				let contact = Self::wall_contact(cache,solids,&down);
+				let entity = contact.map(|contact| contact.other);
+
+				if entity.is_some() != wall.entity.is_some(){
+					wall.time = 0.0;
+				}else{
+					wall.time+= delta_time;
+				}
+				wall.entity = entity;
+				wall.normal = contact.map_or(zero(),|contact| -contact.normal);
+			}
+
+			//Update the states of the character controllers from what they are touching
+			for(
+				entity,
+				mover,
+				ground,
+			) in (
+				&**entities,
+				&mut *movers,
+				&*grounds,
+			).join(){
+				let (velocity,down,submerged) = solids.get(entity).map_or((zero(),Vector2::new(0.0,1.0),0.0),|solid| (solid.velocity,solid.down(),solid.submerged));
+
+				let state = match mover.state{
+					//Climbing is only started and stopped by the controller
+					data::MoverState::Climbing => data::MoverState::Climbing,
+					//Jumping until starting to fall down. The ground from before the jump may still be touched in the first steps
+					data::MoverState::Jumping if dot(&velocity,&down) < 0.0 => data::MoverState::Jumping,
+					data::MoverState::Ducking{..} if ground.is_grounded() => data::MoverState::Ducking{normal: ground.normal},
+					_ if ground.is_grounded() => data::MoverState::OnGround{normal: ground.normal},
+					_ if submerged >= tuning.swim_submerged => data::MoverState::Swimming,
+					_ => data::MoverState::Falling,
+				};
+				mover.set_state(state,time);
+
+				let direction = if velocity.norm_squared() > 0.0{velocity.normalize()}else{zero()};
+				let movement = match walls.get(entity){
+					Some(wall) if wall.is_touching() => data::MoverMovement::Colliding{direction: direction,normal: wall.normal},
+					_ => data::MoverMovement::Freely{direction: direction},
+				};
+				mover.set_movement(movement,time);
+			}
+
+			//Apply friction along the contact tangents. The friction is relative to the surface of the other solid, and kinematic solids carry what they touch by moving it, so their velocity is not included.
+			//The friction is proportional to how hard the solver had to push the contact apart in this step, so it works with any gravity and against walls. Touching multiple solids does not multiply it, since each contact only resolves its own part.
+			//The impulse is limited to what the gravity and acceleration of the solid press into the contact in a step. Otherwise an impact, like landing from a jump, would stop all sliding at once.
+			for &this in &movers{
+				let (mut velocity,material,pressing) = match solids.get(this){
+					Some(solid) => (solid.velocity,solid.material,(if solid.gravity{solid.current_gravity}else{zero()} + solid.acceleration).multiply_by(delta_time)),
+					None => continue,
+				};
+				let contacts = match collision_caches.get(this){
+					Some(cache) => &cache.contacts,
+					None => continue,
+				};
+				//The controller decides how fast to slide down a wall
+				let sliding_wall = walls.get(this).and_then(|wall| if wall.sliding{wall.entity}else{None});
+
+				for contact in contacts{
+					let other = match solids.get(contact.other){
+						Some(other) if contact.impulse > 0.0 && sliding_wall!=Some(contact.other) => other,
+						_ => continue,
+					};
+
+					let tangent = util::vector_perpendicular(-contact.normal);
+					let surface_velocity = tangent.multiply_by(other.surface_velocity) + if other.check_movement{other.velocity}else{zero()};
+					let sliding = dot(&(velocity - surface_velocity),&tangent);
+
+					let impulse = contact.impulse.min(dot(&pressing,&contact.normal).max(0.0));
+					let (static_friction,kinetic_friction) = material.combine(&other.material);
+					velocity-= tangent.multiply_by(if sliding.abs() <= static_friction*impulse{
+						sliding
+					}else{
+						sliding.signum() * kinetic_friction*impulse
+					});
+				}
+
+				if let Some(solid) = solids.get_mut(this){
+					solid.velocity = velocity;
+				}
+			}
+
+			//Apply air friction, and the drag of fluids for the submerged part
+			for(
+				entity,
 				&mut components::Solid{ref mut velocity,..},
-				&mut components::CollisionCache{ref mut position_resolve,ref mut velocity_resolve,ref mut friction_total,..},
+				_,
 			) in (
-				&mut positions,
-				&mut solids,
-				&mut collision_caches,
+				&**entities,
+				&mut *solids,
+				&*collision_caches,
 			).join(){
-				*position = *position + *position_resolve;
+				let (submerged,_,drag) = submersions.get(&entity).cloned().unwrap_or((0.0,0.0,0.0));
 				*velocity = util::vector_lengthen(
-					*velocity + *velocity_resolve,
-					-(Self::AIR_FRICTION + *friction_total)*delta_time
+					*velocity,
+					-tuning.air_friction*(1.0 - submerged)*delta_time
 				);
+				*velocity*= (1.0 - drag*submerged*delta_time).max(0.0);
+			}
+		}
+	}
+	impl<'a> System<'a> for Physics{
+		type SystemData = (
+			ecs::Entities<'a>,
+			ecs::WriteStorage<'a,components::CollisionCache>,
+			ecs::WriteStorage<'a,components::GroundContact>,
+			ecs::WriteStorage<'a,components::WallContact>,
+			ecs::WriteStorage<'a,components::Mover>,
+			ecs::WriteStorage<'a,components::Position>,
+			ecs::WriteStorage<'a,components::Solid>,
+			ecs::WriteStorage<'a,components::Trigger>,
+			ecs::WriteStorage<'a,components::PathMover>,
+			ecs::ReadStorage<'a,components::GravityZone>,
+			ecs::ReadStorage<'a,components::Fluid>,
+			ecs::Fetch<'a,Time>,
+			ecs::Fetch<'a,data::Tuning>,
+			ecs::FetchMut<'a,data::PhysicsTime>,
+			ecs::FetchMut<'a,data::Broadphase>,
+			ecs::FetchMut<'a,data::Events<data::CollisionEvent>>,
+			ecs::FetchMut<'a,data::Events<data::TriggerEvent>>,
+			ecs::FetchMut<'a,data::Events<data::CrushEvent>>
+		);
+		fn run(&mut self,(entities,mut collision_caches,mut grounds,mut walls,mut movers,mut positions,mut solids,mut triggers,mut path_movers,gravity_zones,fluids,time,tuning,mut physics_time,mut broadphase,mut collision_events,mut trigger_events,mut crush_events) : Self::SystemData){
+			use nalgebra::zero;
+			use util;
+
+			//New solids start out at their current position, so that they are not drawn moving from the origin before their first step
+			self.placed.retain(|&entity| entities.is_alive(entity) && solids.get(entity).is_some());
+			for(
+				entity,
+				&components::Position(position),
+				solid,
+			) in (
+				&*entities,
+				&positions,
+				&mut solids,
+			).join(){
+				if self.placed.insert(entity){
+					solid.old_position = position;
+					solid.old_angle    = solid.angle;
+				}
 			}
+
+			physics_time.accumulator+= util::duration_seconds(time.delta_time);
+			collision_events.clear();
+			trigger_events.clear();
+			crush_events.clear();
+
+			//Run as many fixed steps as the accumulated time allows
+			let mut steps = 0;
+			while physics_time.accumulator >= physics_time.step{
+				if steps >= physics_time.max_steps{
+					//Drop the time that could not be simulated to prevent falling further behind
+					physics_time.accumulator%= physics_time.step;
+					break;
+				}
+				physics_time.time+= physics_time.step;
+				Self::step(
+					physics_time.step,
+					physics_time.time,
+					&tuning,
+					&entities,
+					&mut broadphase,
+					&mut collision_events,
+					&mut trigger_events,
+					&mut crush_events,
+					&mut collision_caches,
+					&mut grounds,
+					&mut walls,
+					&mut movers,
+					&mut positions,
+					&mut solids,
+					&mut triggers,
+					&mut path_movers,
+					&gravity_zones,
+					&fluids,
+				);
+				physics_time.accumulator-= physics_time.step;
+				steps+= 1;
+			}
+
+			//Reset the acceleration from other systems for the next frame. It is kept when no step ran so that it is not lost on short frames
+			if steps > 0{
+				for &mut components::Solid{ref mut acceleration,..} in (&mut solids).join(){
+					*acceleration = zero();
+				}
+			}
+		}
+	}
+
+	#[cfg(test)]
+	mod tests{
+		use headless::Simulation;
+		use nalgebra::Vector2;
+
+		use *;
+
+		const WALL_LEFT: f64 = 134.0;
+
+		//A floor with its top at y = -16, and a wall standing on it with its left side at WALL_LEFT
+		fn walled_floor(sim: &mut Simulation){
+			sim.add_block(Vector2::new(0.0,0.0),Vector2::new(600.0,16.0),data::Material::NORMAL);
+			sim.add_block(Vector2::new(WALL_LEFT + 16.0,-116.0),Vector2::new(16.0,100.0),data::Material::NORMAL);
+		}
+
+		//Pushes the first player towards the wall every step, and checks that none of the players end up inside the wall or each other
+		fn push_chain(xs: &[f64]){
+			let mut sim = Simulation::new();
+			walled_floor(&mut sim);
+			let players: Vec<_> = xs.iter().map(|&x| sim.add_player(1,Vector2::new(x,-48.0))).collect();
+			let pusher = players[0];
+			sim.run(240,|_,sim|{
+				let velocity = sim.velocity(pusher);
+				sim.set_velocity(pusher,Vector2::new(200.0,velocity[1]));
+			});
+
+			for pair in players.windows(2){
+				let left_right = sim.position(pair[0])[0] + 16.0;
+				let right_left = sim.position(pair[1])[0] - 16.0;
+				assert!(left_right <= right_left + 0.5,"The players overlap by {} pixels",left_right - right_left);
+			}
+			for &player in &players{
+				let right = sim.position(player)[0] + 16.0;
+				assert!(right <= WALL_LEFT + 0.5,"Pushed {} pixels into the wall",right - WALL_LEFT);
+			}
+		}
+
+		//Player B walking into player A, who is standing against a wall
+		#[test]
+		fn push_into_wall(){
+			push_chain(&[40.0,WALL_LEFT - 16.0]);
+		}
+
+		#[test]
+		fn push_chain_into_wall(){
+			push_chain(&[0.0,50.0,WALL_LEFT - 16.0]);
 		}
 	}
 }
@@ -5,7 +5,10 @@ use amethyst::assets::{AssetFuture,BoxedErr,Context,Format,Loader};
 use amethyst::ecs::rendering::{MaterialComponent,TextureContext};
 use amethyst::renderer::vertex::PosNormTex;
 use futures::{Future,IntoFuture};
-use nalgebra::Vector2;
+use nalgebra::{Isometry2,Point2,Vector2};
+use ncollide::bounding_volume::AABB;
+use ncollide::shape::Shape;
+use std::time::Duration;
 
 pub fn gen_rectangle_glvertices(w: f32,h: f32) -> Vec<PosNormTex>{
 	vec![
@@ -43,6 +46,67 @@ pub fn gen_rectangle_glvertices(w: f32,h: f32) -> Vec<PosNormTex>{
 	]
 }
 
+//Triangle fan of a convex polygon. The points may be in any order
+pub fn gen_polygon_glvertices(points: &[Point2<f64>]) -> Vec<PosNormTex>{
+	use std::cmp::Ordering;
+
+	if points.len() < 3{
+		return Vec::new();
+	}
+
+	//Sort the points around the centroid, in the same winding as the rectangles
+	let centroid = points.iter().fold(Vector2::new(0.0,0.0),|sum,p| sum + p.coords) / points.len() as f64;
+	let angle = |p: &Point2<f64>| (p[1] - centroid[1]).atan2(p[0] - centroid[0]);
+	let mut points = points.to_vec();
+	points.sort_by(|a,b| angle(b).partial_cmp(&angle(a)).unwrap_or(Ordering::Equal));
+
+	//Texture coordinates are relative to the bounding box
+	let (mins,maxs) = points.iter().fold((points[0],points[0]),|(mins,maxs),p|(
+		Point2::new(mins[0].min(p[0]),mins[1].min(p[1])),
+		Point2::new(maxs[0].max(p[0]),maxs[1].max(p[1])),
+	));
+	let tex_coord = |x: f64,min: f64,max: f64| if max > min{((x - min) / (max - min)) as f32}else{0.0};
+	let vertex = |p: &Point2<f64>| PosNormTex{
+		a_position: [p[0] as f32, p[1] as f32, 0.0],
+		a_normal: [0.0, 0.0, 1.0],
+		a_tex_coord: [
+			tex_coord(p[0],mins[0],maxs[0]),
+			tex_coord(p[1],mins[1],maxs[1]),
+		],
+	};
+
+	let mut vertices = Vec::with_capacity((points.len()-2)*3);
+	for i in 1 .. points.len()-1{
+		vertices.push(vertex(&points[0]));
+		vertices.push(vertex(&points[i]));
+		vertices.push(vertex(&points[i+1]));
+	}
+	vertices
+}
+
+//Vertices matching the shape in its local coordinates. Other shapes are drawn as their bounding box
+pub fn gen_shape_glvertices(shape: &Shape<Point2<f64>,Isometry2<f64>>) -> Vec<PosNormTex>{
+	use ncollide::shape::{ConvexHull,Cuboid,Triangle};
+
+	if let Some(cuboid) = shape.as_shape::<Cuboid<Vector2<f64>>>(){
+		let half_extents = cuboid.half_extents();
+		gen_rectangle_glvertices((half_extents[0]*2.0) as f32,(half_extents[1]*2.0) as f32)
+	}else if let Some(polygon) = shape.as_shape::<ConvexHull<Point2<f64>>>(){
+		gen_polygon_glvertices(polygon.points())
+	}else if let Some(triangle) = shape.as_shape::<Triangle<Point2<f64>>>(){
+		gen_polygon_glvertices(&[*triangle.a(),*triangle.b(),*triangle.c()])
+	}else{
+		let aabb = shape.aabb(&Isometry2::identity());
+		let (mins,maxs) = (aabb.mins(),aabb.maxs());
+		gen_polygon_glvertices(&[
+			Point2::new(mins[0],mins[1]),
+			Point2::new(maxs[0],mins[1]),
+			Point2::new(maxs[0],maxs[1]),
+			Point2::new(mins[0],maxs[1]),
+		])
+	}
+}
+
 pub fn vector_lengthen(v: Vector2<f64>,x: f64) -> Vector2<f64>{
 	use alga::general::AbstractModule;
 	use nalgebra::zero;
@@ -61,6 +125,70 @@ pub fn vector_perpendicular(v: Vector2<f64>) -> Vector2<f64>{
 	Vector2::new(-v[1],v[0])
 }
 
+#[inline(always)]
+pub fn vector_rotate(v: Vector2<f64>,angle: f64) -> Vector2<f64>{
+	let (sin,cos) = angle.sin_cos();
+	Vector2::new(v[0]*cos - v[1]*sin,v[0]*sin + v[1]*cos)
+}
+
+//Direction of a gravity acceleration, or downwards when there is no gravity
+#[inline(always)]
+pub fn gravity_down(gravity: Vector2<f64>) -> Vector2<f64>{
+	if gravity.norm_squared() > 0.0{
+		gravity.normalize()
+	}else{
+		Vector2::new(0.0,1.0)
+	}
+}
+
+//How far the bounding box reaches in a direction (the maximum projection of its corners)
+pub fn aabb_extent(aabb: &AABB<Point2<f64>>,direction: &Vector2<f64>) -> f64{
+	let mins = aabb.mins();
+	let maxs = aabb.maxs();
+	(if direction[0] >= 0.0{maxs[0]}else{mins[0]}) * direction[0] +
+	(if direction[1] >= 0.0{maxs[1]}else{mins[1]}) * direction[1]
+}
+
+//How much of the first bounding box is inside the second one (0.0 to 1.0)
+pub fn aabb_overlap(aabb: &AABB<Point2<f64>>,other: &AABB<Point2<f64>>) -> f64{
+	let (mins,maxs) = (aabb.mins(),aabb.maxs());
+	let width  = (maxs[0].min(other.maxs()[0]) - mins[0].max(other.mins()[0])).max(0.0);
+	let height = (maxs[1].min(other.maxs()[1]) - mins[1].max(other.mins()[1])).max(0.0);
+	let area   = (maxs[0] - mins[0]) * (maxs[1] - mins[1]);
+	if area > 0.0{
+		(width*height / area).min(1.0)
+	}else{
+		0.0
+	}
+}
+
+//The range of multiples of the direction where the ray from the origin is inside the bounding box, or None when it misses it
+pub fn ray_aabb(origin: Vector2<f64>,direction: Vector2<f64>,aabb: &AABB<Point2<f64>>) -> Option<(f64,f64)>{
+	use std::f64::{INFINITY,NEG_INFINITY};
+
+	let (mins,maxs) = (aabb.mins(),aabb.maxs());
+	let mut enter = NEG_INFINITY;
+	let mut exit  = INFINITY;
+	for i in 0..2{
+		if direction[i]==0.0{
+			if origin[i] < mins[i] || origin[i] > maxs[i]{
+				return None;
+			}
+		}else{
+			let a = (mins[i] - origin[i]) / direction[i];
+			let b = (maxs[i] - origin[i]) / direction[i];
+			enter = enter.max(a.min(b));
+			exit  = exit.min(a.max(b));
+		}
+	}
+	if enter <= exit{Some((enter,exit))}else{None}
+}
+
+#[inline(always)]
+pub fn duration_seconds(d: Duration) -> f64{
+	d.as_secs() as f64 + d.subsec_nanos() as f64 / 1.0e9
+}
+
 pub fn load_proc_asset<T,F>(engine: &mut amethyst::Engine,f: F) -> AssetFuture<T::Item> where
 	T: IntoFuture<Error = BoxedErr>,
 	T::Future: 'static,